@@ -0,0 +1,2 @@
+ALTER TABLE _background_tasks ADD COLUMN IF NOT EXISTS priority INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS _background_tasks_priority_idx ON _background_tasks (priority DESC, id);
@@ -0,0 +1 @@
+ALTER TABLE _background_tasks ADD COLUMN IF NOT EXISTS run_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS _background_tasks_dead (
+  id BIGINT PRIMARY KEY NOT NULL,
+  job_type TEXT NOT NULL,
+  is_async BOOLEAN NOT NULL,
+  priority INTEGER NOT NULL,
+  data BYTEA NOT NULL,
+  retries INTEGER NOT NULL,
+  error TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL,
+  died_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
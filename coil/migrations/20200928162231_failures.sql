@@ -0,0 +1,14 @@
+ALTER TABLE _background_tasks ADD COLUMN IF NOT EXISTS last_error TEXT;
+
+CREATE TABLE IF NOT EXISTS _background_tasks_history (
+  id BIGSERIAL PRIMARY KEY NOT NULL,
+  task_id BIGINT NOT NULL,
+  job_type TEXT NOT NULL,
+  attempt INTEGER NOT NULL,
+  error TEXT,
+  panic TEXT,
+  worker TEXT NOT NULL,
+  duration INTERVAL NOT NULL,
+  finished_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS _background_tasks_history_task_id_idx ON _background_tasks_history (task_id);
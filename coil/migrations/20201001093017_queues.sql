@@ -0,0 +1,3 @@
+ALTER TABLE _background_tasks ADD COLUMN IF NOT EXISTS queue TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE _background_tasks_dead ADD COLUMN IF NOT EXISTS queue TEXT NOT NULL DEFAULT 'default';
+CREATE INDEX IF NOT EXISTS _background_tasks_queue_idx ON _background_tasks (queue, priority DESC, id);
@@ -0,0 +1,12 @@
+CREATE OR REPLACE FUNCTION _background_tasks_notify() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM pg_notify('_background_tasks', NEW.queue);
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER _background_tasks_notify
+  AFTER INSERT ON _background_tasks
+  FOR EACH ROW
+  WHEN (NEW.run_at <= NOW())
+  EXECUTE PROCEDURE _background_tasks_notify();
@@ -0,0 +1,2 @@
+ALTER TABLE _background_tasks ADD COLUMN IF NOT EXISTS locked_by TEXT;
+ALTER TABLE _background_tasks ADD COLUMN IF NOT EXISTS locked_until TIMESTAMP;
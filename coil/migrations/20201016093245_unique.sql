@@ -0,0 +1,2 @@
+ALTER TABLE _background_tasks ADD COLUMN IF NOT EXISTS is_unique BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE UNIQUE INDEX IF NOT EXISTS _background_tasks_unique_idx ON _background_tasks (job_type, md5(data)) WHERE is_unique;
@@ -0,0 +1 @@
+ALTER TABLE _background_tasks ADD COLUMN IF NOT EXISTS lease_token TEXT;
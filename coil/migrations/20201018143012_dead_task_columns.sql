@@ -0,0 +1,2 @@
+ALTER TABLE _background_tasks_dead ADD COLUMN IF NOT EXISTS is_unique BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE _background_tasks_dead ADD COLUMN IF NOT EXISTS trace_context TEXT;
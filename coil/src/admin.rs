@@ -0,0 +1,451 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of coil.
+
+// coil is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// coil is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with coil.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Inspecting and administering the queue, for dashboards and operators
+
+use crate::db::{self, from_epoch};
+use crate::error::Error;
+use sqlx::prelude::*;
+use sqlx::PgPool;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use crate::db::{dead_jobs, get_dead_job, purge_dead_jobs, resurrect_dead_job, DeadJob};
+
+/// What a job in the queue is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum JobState {
+    /// Due to run, waiting for a worker
+    Pending,
+    /// Enqueued to run later
+    Scheduled,
+    /// Leased to a worker running it
+    Running,
+    /// Failed at least once, waiting to be retried
+    Failed,
+    /// Ran out of retries, and was moved to the dead letter table
+    Dead,
+}
+
+impl JobState {
+    fn from_sql(state: &str) -> Self {
+        match state {
+            "scheduled" => JobState::Scheduled,
+            "running" => JobState::Running,
+            "failed" => JobState::Failed,
+            "dead" => JobState::Dead,
+            _ => JobState::Pending,
+        }
+    }
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            JobState::Pending => "pending",
+            JobState::Scheduled => "scheduled",
+            JobState::Running => "running",
+            JobState::Failed => "failed",
+            JobState::Dead => "dead",
+        })
+    }
+}
+
+/// The state of a job, as a SQL expression over `_background_tasks`
+const STATE: &str = "CASE WHEN locked_until > NOW() THEN 'running'
+    WHEN retries > 0 THEN 'failed'
+    WHEN run_at > NOW() THEN 'scheduled'
+    ELSE 'pending' END";
+
+/// A job in the queue
+#[derive(Debug, Clone)]
+pub struct QueuedJob {
+    pub id: i64,
+    pub job_type: String,
+    /// MessagePack encoded job data
+    pub data: Vec<u8>,
+    /// Whether the job is run with `perform_async`
+    pub is_async: bool,
+    pub priority: i32,
+    pub queue: String,
+    /// How many times the job failed
+    pub retries: i32,
+    /// The error of the last failed attempt
+    pub last_error: Option<String>,
+    pub state: JobState,
+    /// When the job was enqueued
+    pub created_at: SystemTime,
+    /// When the job is due to run
+    pub run_at: SystemTime,
+    /// The worker the job is leased to, if it was ever claimed
+    pub locked_by: Option<String>,
+    /// When the lease on the job expires
+    pub locked_until: Option<SystemTime>,
+}
+
+#[derive(FromRow)]
+struct QueuedJobRow {
+    id: i64,
+    job_type: String,
+    data: Vec<u8>,
+    is_async: bool,
+    priority: i32,
+    queue: String,
+    retries: i32,
+    last_error: Option<String>,
+    state: String,
+    created_at: f64,
+    run_at: f64,
+    locked_by: Option<String>,
+    locked_until: Option<f64>,
+}
+
+impl From<QueuedJobRow> for QueuedJob {
+    fn from(row: QueuedJobRow) -> Self {
+        Self {
+            id: row.id,
+            job_type: row.job_type,
+            data: row.data,
+            is_async: row.is_async,
+            priority: row.priority,
+            queue: row.queue,
+            retries: row.retries,
+            last_error: row.last_error,
+            state: JobState::from_sql(&row.state),
+            created_at: from_epoch(row.created_at),
+            run_at: from_epoch(row.run_at),
+            locked_by: row.locked_by,
+            locked_until: row.locked_until.map(from_epoch),
+        }
+    }
+}
+
+/// A job in the queue or the dead letter table, without its data
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobSummary {
+    pub id: i64,
+    pub job_type: String,
+    pub queue: String,
+    pub state: JobState,
+    /// The error of the last failed attempt
+    pub last_error: Option<String>,
+}
+
+fn select_jobs(condition: &str) -> String {
+    format!(
+        "SELECT id, job_type, data, is_async, priority, queue, retries, last_error,
+            {} AS state,
+            EXTRACT(EPOCH FROM created_at::timestamptz)::FLOAT8 AS created_at,
+            EXTRACT(EPOCH FROM run_at::timestamptz)::FLOAT8 AS run_at,
+            locked_by,
+            EXTRACT(EPOCH FROM locked_until::timestamptz)::FLOAT8 AS locked_until
+        FROM _background_tasks
+        WHERE {}
+        ORDER BY id",
+        STATE, condition
+    )
+}
+
+/// The jobs an admin function applies to. Every filter that is set has to match.
+///
+/// # Example
+/// ```ignore
+/// let failed_mails = JobFilter::new().job_type("send_mail").failed_only();
+/// for job in coil::admin::list_jobs(&pool, &failed_mails).await? {
+///     println!("{}: {:?}", job.id, job.last_error);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct JobFilter {
+    job_type: Option<String>,
+    queue: Option<String>,
+    failed_only: bool,
+    created_before: Option<SystemTime>,
+    limit: Option<i64>,
+}
+
+impl JobFilter {
+    /// A filter matching every job
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only jobs of `job_type`
+    pub fn job_type(mut self, job_type: impl Into<String>) -> Self {
+        self.job_type = Some(job_type.into());
+        self
+    }
+
+    /// Only jobs enqueued on `queue`
+    pub fn queue(mut self, queue: impl Into<String>) -> Self {
+        self.queue = Some(queue.into());
+        self
+    }
+
+    /// Only jobs which failed at least once
+    pub fn failed_only(mut self) -> Self {
+        self.failed_only = true;
+        self
+    }
+
+    /// Only jobs enqueued before `time`
+    pub fn created_before(mut self, time: SystemTime) -> Self {
+        self.created_before = Some(time);
+        self
+    }
+
+    /// At most `limit` jobs, the oldest first
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit.into());
+        self
+    }
+
+    fn created_before_secs(&self) -> Option<f64> {
+        self.created_before.map(|time| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        })
+    }
+}
+
+/// The jobs in `_background_tasks` matching the filter bound to `$1` to `$5`
+const FILTERED_IDS: &str = "SELECT id FROM _background_tasks
+    WHERE ($1::TEXT IS NULL OR job_type = $1) AND ($2::TEXT IS NULL OR queue = $2)
+        AND (NOT $3 OR retries > 0)
+        AND ($4::FLOAT8 IS NULL OR created_at < to_timestamp($4))
+    ORDER BY id LIMIT $5";
+
+macro_rules! bind_filter {
+    ($query:expr, $filter:expr) => {
+        $query
+            .bind($filter.job_type.as_deref())
+            .bind($filter.queue.as_deref())
+            .bind($filter.failed_only)
+            .bind($filter.created_before_secs())
+            .bind($filter.limit)
+    };
+}
+
+/// List the jobs in the queue matching `filter`, oldest first.
+/// Jobs which ran out of retries are listed by `dead_jobs`.
+pub async fn list_jobs(pool: &PgPool, filter: &JobFilter) -> Result<Vec<QueuedJob>, Error> {
+    let sql = select_jobs(&format!("id IN ({})", FILTERED_IDS));
+    let rows = bind_filter!(sqlx::query_as::<_, QueuedJobRow>(&sql), filter)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(QueuedJob::from).collect())
+}
+
+/// Get the job in the queue with ID `id`
+pub async fn get_job(pool: &PgPool, id: i64) -> Result<Option<QueuedJob>, Error> {
+    let row = sqlx::query_as::<_, QueuedJobRow>(&select_jobs("id = $1"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(QueuedJob::from))
+}
+
+/// Summarize the jobs in the queue matching `filter`, oldest first.
+/// Like `list_jobs`, without loading the data of every job.
+pub async fn list_job_summaries(
+    pool: &PgPool,
+    filter: &JobFilter,
+) -> Result<Vec<JobSummary>, Error> {
+    let sql = format!(
+        "SELECT id, job_type, queue, {} AS state, last_error FROM _background_tasks
+        WHERE id IN ({})
+        ORDER BY id",
+        STATE, FILTERED_IDS
+    );
+    let rows = bind_filter!(
+        sqlx::query_as::<_, (i64, String, String, String, Option<String>)>(&sql),
+        filter
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, job_type, queue, state, last_error)| JobSummary {
+            id,
+            job_type,
+            queue,
+            state: JobState::from_sql(&state),
+            last_error,
+        })
+        .collect())
+}
+
+/// Summarize the dead jobs among `ids`, with the error they died of as `last_error`
+pub async fn dead_job_summaries(pool: &PgPool, ids: &[i64]) -> Result<Vec<JobSummary>, Error> {
+    let rows = sqlx::query_as::<_, (i64, String, String, String)>(
+        "SELECT id, job_type, queue, error FROM _background_tasks_dead
+        WHERE id = ANY($1)
+        ORDER BY id",
+    )
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, job_type, queue, error)| JobSummary {
+            id,
+            job_type,
+            queue,
+            state: JobState::Dead,
+            last_error: Some(error),
+        })
+        .collect())
+}
+
+/// How many jobs of a type are in a state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobCount {
+    pub job_type: String,
+    pub state: JobState,
+    pub count: i64,
+}
+
+/// Count the jobs of each type in each state, dead jobs included
+pub async fn count_jobs(pool: &PgPool) -> Result<Vec<JobCount>, Error> {
+    let sql = format!(
+        "SELECT job_type, {} AS state, COUNT(*) FROM _background_tasks GROUP BY 1, 2
+        UNION ALL
+        SELECT job_type, 'dead', COUNT(*) FROM _background_tasks_dead GROUP BY 1",
+        STATE
+    );
+    let rows = sqlx::query_as::<_, (String, String, i64)>(&sql)
+        .fetch_all(pool)
+        .await?;
+    let mut counts: Vec<_> = rows
+        .into_iter()
+        .map(|(job_type, state, count)| JobCount {
+            job_type,
+            state: JobState::from_sql(&state),
+            count,
+        })
+        .collect();
+    counts.sort_by(|a, b| (&a.job_type, a.state).cmp(&(&b.job_type, b.state)));
+    Ok(counts)
+}
+
+/// Delete the job in the queue with ID `id`. Returns `false` if there was none.
+///
+/// If the job is running, the worker running it reports it as lost to its `on_error` hook
+/// once it finishes.
+pub async fn delete_job(pool: &PgPool, id: i64) -> Result<bool, Error> {
+    let done = sqlx::query("DELETE FROM _background_tasks WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(done.rows_affected() > 0)
+}
+
+/// Delete the jobs in the queue matching `filter`. Returns how many jobs were deleted.
+pub async fn delete_jobs(pool: &PgPool, filter: &JobFilter) -> Result<u64, Error> {
+    let sql = format!(
+        "DELETE FROM _background_tasks WHERE id IN ({})",
+        FILTERED_IDS
+    );
+    let done = bind_filter!(sqlx::query(&sql), filter)
+        .execute(pool)
+        .await?;
+    Ok(done.rows_affected())
+}
+
+/// Make the job with ID `id` due to run now, keeping its retry counter.
+/// A job which ran out of retries is moved back into the queue with its retry counter reset.
+/// Returns `false` if there was no such job.
+pub async fn retry_job(pool: &PgPool, id: i64) -> Result<bool, Error> {
+    let done = sqlx::query("UPDATE _background_tasks SET run_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    if done.rows_affected() > 0 {
+        return Ok(true);
+    }
+    resurrect_dead_job(pool, id).await
+}
+
+/// Make the jobs matching `filter` due to run now, like `retry_job`,
+/// dead jobs matching the type, queue and creation time included.
+/// The limit of `filter` applies to queued and dead jobs together, lowest IDs first.
+/// Returns how many jobs were retried.
+pub async fn retry_jobs(pool: &PgPool, filter: &JobFilter) -> Result<u64, Error> {
+    let sql = format!(
+        "WITH picked AS (
+            SELECT id, FALSE AS is_dead FROM _background_tasks
+            WHERE ($1::TEXT IS NULL OR job_type = $1) AND ($2::TEXT IS NULL OR queue = $2)
+                AND (NOT $3 OR retries > 0)
+                AND ($4::FLOAT8 IS NULL OR created_at < to_timestamp($4))
+            UNION ALL
+            SELECT id, TRUE AS is_dead FROM _background_tasks_dead
+            WHERE ($1::TEXT IS NULL OR job_type = $1) AND ($2::TEXT IS NULL OR queue = $2)
+                AND ($4::FLOAT8 IS NULL OR created_at < to_timestamp($4))
+            ORDER BY id LIMIT $5
+        ),
+        queued AS (
+            UPDATE _background_tasks SET run_at = NOW()
+            WHERE id IN (SELECT id FROM picked WHERE NOT is_dead)
+            RETURNING id
+        ),
+        dead AS (
+            DELETE FROM _background_tasks_dead
+            WHERE id IN (SELECT id FROM picked WHERE is_dead)
+            RETURNING *
+        ),
+        {}
+        SELECT (SELECT COUNT(*) FROM queued) + (SELECT COUNT(*) FROM dead)",
+        db::RESURRECT_DEAD
+    );
+    let (retried,) = bind_filter!(sqlx::query_as::<_, (i64,)>(&sql), filter)
+        .fetch_one(pool)
+        .await?;
+    Ok(retried as u64)
+}
+
+/// Put the job with ID `id` back in the queue as if it was just enqueued:
+/// due now, with its retry counter and last error cleared, and not leased to any worker.
+/// A job which ran out of retries is moved back into the queue.
+/// Returns `false` if there was no such job.
+///
+/// A job which is running may be claimed again right away, and the worker running it
+/// reports it as lost to its `on_error` hook once it finishes.
+pub async fn requeue_job(pool: &PgPool, id: i64) -> Result<bool, Error> {
+    let done = sqlx::query(
+        "UPDATE _background_tasks
+        SET run_at = NOW(), retries = 0, last_error = NULL,
+            locked_by = NULL, locked_until = NULL, lease_token = NULL
+        WHERE id = $1",
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+    if done.rows_affected() > 0 {
+        return Ok(true);
+    }
+    resurrect_dead_job(pool, id).await
+}
+
+/// Reset the retry counter of the job in the queue with ID `id`,
+/// so it gets all of its retries again. Returns `false` if there was no such job.
+pub async fn reset_retries(pool: &PgPool, id: i64) -> Result<bool, Error> {
+    let done = sqlx::query("UPDATE _background_tasks SET retries = 0 WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(done.rows_affected() > 0)
+}
@@ -0,0 +1,129 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of coil.
+
+// coil is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// coil is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with coil.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Policies for how long to wait before retrying a failed job
+
+use rand::Rng;
+use std::time::Duration;
+
+/// How long a failed job waits before it may be picked up again.
+///
+/// A job that fails is rescheduled to run no earlier than the delay returned by
+/// [`Backoff::delay`] for the number of times it has already been retried.
+///
+/// # Example
+/// ```ignore
+/// Runner::builder(env, executor, &pool)
+///     .backoff(Backoff::exponential(Duration::from_secs(30)).max_delay(Duration::from_secs(3600)))
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    exponential: bool,
+    jitter: bool,
+}
+
+impl Backoff {
+    /// Double the delay on every retry, starting with `base`.
+    pub const fn exponential(base: Duration) -> Self {
+        Self {
+            base,
+            max: Duration::from_secs(60 * 60 * 24),
+            exponential: true,
+            jitter: false,
+        }
+    }
+
+    /// Wait the same amount of time before every retry.
+    pub const fn constant(delay: Duration) -> Self {
+        Self {
+            base: delay,
+            max: delay,
+            exponential: false,
+            jitter: false,
+        }
+    }
+
+    /// Retry failed jobs as soon as a runner picks them up.
+    pub const fn none() -> Self {
+        Self::constant(Duration::from_secs(0))
+    }
+
+    /// The longest a job will ever wait between retries. Defaults to one day.
+    pub const fn max_delay(mut self, max: Duration) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// Randomize each delay to somewhere between half and all of its value,
+    /// so jobs that failed together don't all retry at the same instant.
+    pub const fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// The delay before the next attempt of a job which has been retried `retries` times
+    pub fn delay(&self, retries: u32) -> Duration {
+        let delay = if self.exponential {
+            2u32.checked_pow(retries)
+                .and_then(|factor| self.base.checked_mul(factor))
+                .unwrap_or(self.max)
+        } else {
+            self.base
+        };
+        let delay = std::cmp::min(delay, self.max);
+
+        if self.jitter && delay > Duration::from_secs(0) {
+            let secs = delay.as_secs_f64();
+            Duration::from_secs_f64(rand::thread_rng().gen_range(secs / 2.0, secs))
+        } else {
+            delay
+        }
+    }
+}
+
+impl Default for Backoff {
+    /// Exponential backoff starting at one minute, capped at one day.
+    fn default() -> Self {
+        Self::exponential(Duration::from_secs(60))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_backoff_doubles_until_max() {
+        let backoff = Backoff::exponential(Duration::from_secs(1)).max_delay(Duration::from_secs(10));
+        assert_eq!(Duration::from_secs(1), backoff.delay(0));
+        assert_eq!(Duration::from_secs(2), backoff.delay(1));
+        assert_eq!(Duration::from_secs(8), backoff.delay(3));
+        assert_eq!(Duration::from_secs(10), backoff.delay(4));
+        assert_eq!(Duration::from_secs(10), backoff.delay(u32::MAX));
+    }
+
+    #[test]
+    fn jitter_stays_within_half_of_delay() {
+        let backoff = Backoff::constant(Duration::from_secs(10)).jitter(true);
+        for retries in 0..100 {
+            let delay = backoff.delay(retries);
+            assert!(delay >= Duration::from_secs(5) && delay <= Duration::from_secs(10));
+        }
+        assert_eq!(Duration::from_secs(0), Backoff::none().jitter(true).delay(3));
+    }
+}
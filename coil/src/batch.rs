@@ -26,6 +26,7 @@ use sqlx::{
 };
 
 type Result<T> = std::result::Result<T, crate::error::BatchInsertError>;
+type WithFn = Box<dyn Fn(&mut Chunk) -> Result<()> + Send>;
 
 const CHUNK_MAX: usize = 30_000;
 
@@ -42,7 +43,7 @@ pub struct Batch {
     name: &'static str,
     leading: String,
     trailing: String,
-    with: Option<Box<dyn Fn(&mut Chunk) -> Result<()> + Send>>,
+    with: Option<WithFn>,
     chunks: Vec<Chunk>,
     index: usize,
     len: usize,
@@ -104,9 +105,9 @@ impl Batch {
         self.chunks[self.index].append(sql);
     }
 
-    pub fn bind<'a, T: 'a>(&mut self, value: T) -> Result<()>
+    pub fn bind<'a, T>(&mut self, value: T) -> Result<()>
     where
-        T: Encode<'a, Postgres> + Type<Postgres> + Send,
+        T: 'a + Encode<'a, Postgres> + Type<Postgres> + Send,
     {
         self.chunks[self.index].bind(value)
     }
@@ -146,9 +147,9 @@ impl Chunk {
         self.query.push_str(sql);
     }
 
-    pub fn bind<'a, T: 'a>(&mut self, value: T) -> Result<()>
+    pub fn bind<'a, T>(&mut self, value: T) -> Result<()>
     where
-        T: Encode<'a, Postgres> + Type<Postgres> + Send,
+        T: 'a + Encode<'a, Postgres> + Type<Postgres> + Send,
     {
         self.arguments.add(value);
         self.query.push('$');
@@ -159,7 +160,7 @@ impl Chunk {
     }
 
     async fn execute(self, conn: &mut PgConnection) -> Result<u64> {
-        let done = sqlx::query_with(&*self.query, self.arguments.into_arguments())
+        let done = sqlx::query_with(&self.query, self.arguments.into_arguments())
             .execute(conn)
             .await?;
         Ok(done.rows_affected())
@@ -16,10 +16,11 @@
 
 //! Database Operations for getting and deleting jobs
 
-use crate::error::{EnqueueError, Error, PerformError};
+use crate::error::{EnqueueError, Error};
 use crate::job::Job;
 use sqlx::prelude::*;
 use sqlx::Postgres;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(FromRow)]
 pub struct BackgroundJob {
@@ -27,8 +28,49 @@ pub struct BackgroundJob {
     pub job_type: String,
     pub data: Vec<u8>,
     pub is_async: bool,
+    pub retries: i32,
+    pub queue: String,
+    /// Seconds the job waited to be claimed after it became due
+    pub waited: f64,
+    /// The tracing context the job was enqueued from
+    pub trace_context: Option<String>,
+    /// Set anew each time the job is claimed, so that an attempt whose lease expired can't
+    /// record its outcome over the attempt which claimed the job after it
+    pub lease_token: String,
 }
-  
+
+/// When an enqueued job becomes available to runners
+#[derive(Copy, Clone)]
+pub enum RunAt {
+    Now,
+    At(SystemTime),
+    In(Duration),
+}
+
+impl RunAt {
+    /// The absolute time (in seconds since the epoch) and delay (in seconds from `NOW()`)
+    /// bound to the insert query. The delay is applied by Postgres so that it is relative to
+    /// the database clock rather than ours.
+    fn as_bindings(&self) -> (Option<f64>, f64) {
+        match self {
+            RunAt::Now => (None, 0.0),
+            RunAt::At(time) => (
+                Some(
+                    time.duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs_f64(),
+                ),
+                0.0,
+            ),
+            RunAt::In(delay) => (None, delay.as_secs_f64()),
+        }
+    }
+}
+
+/// The channel Postgres notifies on whenever a job that is ready to run is inserted.
+/// The payload is the queue the job was enqueued on.
+pub const NOTIFY_CHANNEL: &str = "_background_tasks";
+
 /// Run the migrations for the background tasks.
 /// This creates a table _background_tasks which stores the tasks for execution
 /// ```sql
@@ -37,31 +79,89 @@ pub struct BackgroundJob {
 ///  job_type TEXT NOT NULL,
 ///  data BYTEA NOT NULL,
 ///  is_async BOOLEAN NOT NULL,
+///  priority INTEGER NOT NULL DEFAULT 0,
+///  queue TEXT NOT NULL DEFAULT 'default',
+///  run_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+///  last_error TEXT,
 ///  retries INTEGER NOT NULL DEFAULT 0,
 ///  last_retry TIMESTAMP NOT NULL DEFAULT '1970-01-01',
 ///  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
 /// );
 /// ```
+///
+/// Inserting a job that is due notifies `NOTIFY_CHANNEL` with the queue of the job,
+/// so that runners started with `Runner::run_forever` pick it up right away.
+///
+/// Jobs with `Job::UNIQUE` set are stored with `is_unique`, and a partial unique index on
+/// `(job_type, md5(data))` keeps a second identical job from being inserted until the first
+/// leaves the table.
+///
+/// Jobs which run out of retries are moved to _background_tasks_dead
+/// ```sql
+/// CREATE TABLE _background_tasks_dead (
+///  id BIGINT PRIMARY KEY NOT NULL,
+///  job_type TEXT NOT NULL,
+///  is_async BOOLEAN NOT NULL,
+///  priority INTEGER NOT NULL,
+///  queue TEXT NOT NULL DEFAULT 'default',
+///  data BYTEA NOT NULL,
+///  retries INTEGER NOT NULL,
+///  error TEXT NOT NULL,
+///  created_at TIMESTAMP NOT NULL,
+///  died_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+/// );
+/// ```
+///
+/// If the runner is built with `keep_history`, every failed attempt is recorded in
+/// _background_tasks_history
+/// ```sql
+/// CREATE TABLE _background_tasks_history (
+///  id BIGSERIAL PRIMARY KEY NOT NULL,
+///  task_id BIGINT NOT NULL,
+///  job_type TEXT NOT NULL,
+///  attempt INTEGER NOT NULL,
+///  error TEXT,
+///  panic TEXT,
+///  worker TEXT NOT NULL,
+///  duration INTERVAL NOT NULL,
+///  finished_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+/// );
+/// ```
 pub async fn migrate(pool: impl Acquire<'_, Database = Postgres>) -> Result<(), Error> {
     sqlx::migrate!("./migrations")
         .run(pool)
         .await
         .map_err(Into::into)
 }
-  
+
 #[cfg(feature = "analyze")]
 pub async fn enqueue_job<T: Job>(
     conn: impl Executor<'_, Database = Postgres>,
     job: T,
+    priority: i32,
+    run_at: RunAt,
 ) -> Result<(), EnqueueError> {
     let data = rmp_serde::encode::to_vec(&job)?;
-    let res = sqlx::query_as::<_, (sqlx::types::Json<serde_json::Value>,)>("EXPLAIN (FORMAT JSON, ANALYZE, BUFFERS) INSERT INTO _background_tasks (job_type, data, is_async) VALUES ($1, $2, $3)")
-        .bind(T::JOB_TYPE)
+    let job_type = T::job_type();
+    let (at, delay) = run_at.as_bindings();
+    let res = sqlx::query_as::<_, (sqlx::types::Json<serde_json::Value>,)>("EXPLAIN (FORMAT JSON, ANALYZE, BUFFERS) INSERT INTO _background_tasks (job_type, data, is_async, priority, run_at, queue, trace_context, is_unique) VALUES ($1, $2, $3, $4, COALESCE(to_timestamp($5), NOW()) + make_interval(secs => $6), $7, $8, $9) ON CONFLICT (job_type, md5(data)) WHERE is_unique DO NOTHING")
+        .bind(&*job_type)
         .bind(data)
         .bind(T::ASYNC)
+        .bind(priority)
+        .bind(at)
+        .bind(delay)
+        .bind(T::QUEUE)
+        .bind(crate::trace::current_context())
+        .bind(T::UNIQUE)
         .fetch_one(conn)
         .await?;
-    log::debug!("EXPLAIN/ANALYZE {}", serde_json::to_string_pretty(&res.0.0).unwrap());
+    #[cfg(feature = "metrics")]
+    crate::metrics::enqueued(&job_type, 1);
+    log::debug!(
+        "EXPLAIN/ANALYZE {}",
+        serde_json::to_string_pretty(&res.0 .0).unwrap()
+    );
     Ok(())
 }
 
@@ -69,108 +169,436 @@ pub async fn enqueue_job<T: Job>(
 pub async fn enqueue_job<T: Job>(
     conn: impl Executor<'_, Database = Postgres>,
     job: T,
+    priority: i32,
+    run_at: RunAt,
 ) -> Result<(), EnqueueError> {
     let data = rmp_serde::encode::to_vec(&job)?;
-    sqlx::query("INSERT INTO _background_tasks (job_type, data, is_async) VALUES ($1, $2, $3)")
-        .bind(T::JOB_TYPE)
-        .bind(data)
-        .bind(T::ASYNC)
-        .execute(conn)
-        .await?;
+    let job_type = T::job_type();
+    let (at, delay) = run_at.as_bindings();
+    let done = sqlx::query(
+        "INSERT INTO _background_tasks (job_type, data, is_async, priority, run_at, queue, trace_context, is_unique)
+        VALUES ($1, $2, $3, $4, COALESCE(to_timestamp($5), NOW()) + make_interval(secs => $6), $7, $8, $9)
+        ON CONFLICT (job_type, md5(data)) WHERE is_unique DO NOTHING",
+    )
+    .bind(&*job_type)
+    .bind(data)
+    .bind(T::ASYNC)
+    .bind(priority)
+    .bind(at)
+    .bind(delay)
+    .bind(T::QUEUE)
+    .bind(crate::trace::current_context())
+    .bind(T::UNIQUE)
+    .execute(conn)
+    .await?;
+    if done.rows_affected() == 0 {
+        log::debug!(
+            "Not enqueueing a {} job identical to one already queued",
+            job_type
+        );
+    }
+    #[cfg(feature = "metrics")]
+    crate::metrics::enqueued(&job_type, done.rows_affected());
     Ok(())
 }
 
-pub async fn enqueue_jobs_batch<T: Job>(conn: &mut sqlx::PgConnection, jobs: Vec<T>) -> Result<(), EnqueueError> {
+pub async fn enqueue_jobs_batch<T: Job>(
+    conn: &mut sqlx::PgConnection,
+    jobs: Vec<T>,
+) -> Result<(), EnqueueError> {
     let mut batch = crate::batch::Batch::new(
         "jobs",
-         r#"INSERT INTO "_background_tasks" (
-            job_type, data, is_async
+        r#"INSERT INTO "_background_tasks" (
+            job_type, data, is_async, priority, queue, trace_context, is_unique
         ) VALUES
          "#,
-         r#""#
+        r#" ON CONFLICT (job_type, md5(data)) WHERE is_unique DO NOTHING"#,
     );
-     
+
+    let job_type = T::job_type();
+    let trace_context = crate::trace::current_context();
     for job in jobs.into_iter() {
         let data = rmp_serde::encode::to_vec(&job)?;
-        batch.reserve(3)?;
+        batch.reserve(7)?;
         if batch.current_num_arguments() > 0 {
             batch.append(",");
         }
         batch.append("(");
-        batch.bind(T::JOB_TYPE)?;
+        batch.bind(&*job_type)?;
         batch.append(",");
         batch.bind(data)?;
         batch.append(",");
         batch.bind(T::ASYNC)?;
+        batch.append(",");
+        batch.bind(T::PRIORITY)?;
+        batch.append(",");
+        batch.bind(T::QUEUE)?;
+        batch.append(",");
+        batch.bind(trace_context.clone())?;
+        batch.append(",");
+        batch.bind(T::UNIQUE)?;
         batch.append(")");
     }
-    batch.execute(conn).await?;
+    let enqueued = batch.execute(conn).await?;
+    log::debug!("Enqueued a batch of {} {} jobs", enqueued, job_type);
+    #[cfg(feature = "metrics")]
+    crate::metrics::enqueued(&job_type, enqueued);
     Ok(())
 }
 
-/// Get the next unlocked job.
-/// Optionally pass a boolean to specify whether to get the next unlocked synchronous or
-/// asynchronous job.
-/// Passing `None` gets the next unlocked job regardless of whether it is async or sync.
-pub async fn find_next_unlocked_job(
+/// Claim up to `limit` jobs that are due to run and aren't leased to a worker, highest priority
+/// first, in a single statement.
+/// Optionally pass a boolean to specify whether to claim synchronous or asynchronous jobs.
+/// Passing `None` claims jobs regardless of whether they are async or sync.
+/// If `queues` is given, only jobs enqueued on one of those queues are considered.
+///
+/// The jobs are leased to `worker` until `lease` has passed. Once the lease on a job expires
+/// without being extended, because the worker died, the job may be claimed again.
+/// Each claim is given a random `lease_token`, which the outcome of the attempt is recorded with.
+pub async fn claim_jobs(
     conn: impl Executor<'_, Database = Postgres>,
     is_async: Option<bool>,
-) -> Result<Option<BackgroundJob>, sqlx::Error> {
-    if let Some(a) = is_async {
-        sqlx::query_as::<_, BackgroundJob>(
-            "SELECT id, job_type, data, is_async
-            FROM _background_tasks
-            WHERE is_async = $1
-            ORDER BY id FOR UPDATE SKIP LOCKED",
-        )
-        .bind(a)
-        .fetch_optional(conn)
-        .await
-        .map_err(Into::into)
-    } else {
-        sqlx::query_as::<_, BackgroundJob>(
-            "SELECT id, job_type, data, is_async
-             FROM _background_tasks
-             ORDER BY id FOR UPDATE SKIP LOCKED",
+    queues: Option<&[String]>,
+    worker: &str,
+    lease: Duration,
+    limit: usize,
+) -> Result<Vec<BackgroundJob>, sqlx::Error> {
+    sqlx::query_as::<_, BackgroundJob>(
+        "WITH claimed AS (
+            UPDATE _background_tasks
+            SET locked_by = $3, locked_until = NOW() + make_interval(secs => $4),
+                lease_token = md5(random()::TEXT || clock_timestamp()::TEXT || id::TEXT)
+            WHERE id IN (
+                SELECT id FROM _background_tasks
+                WHERE ($1::BOOLEAN IS NULL OR is_async = $1) AND run_at <= NOW()
+                    AND (locked_until IS NULL OR locked_until < NOW())
+                    AND ($2::TEXT[] IS NULL OR queue = ANY($2))
+                ORDER BY priority DESC, id
+                LIMIT $5
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, job_type, data, is_async, retries, queue, trace_context, lease_token,
+                priority, run_at
         )
-        .fetch_optional(conn)
-        .await
-        .map_err(Into::into)
-    }
+        SELECT id, job_type, data, is_async, retries, queue, trace_context, lease_token,
+            GREATEST(EXTRACT(EPOCH FROM NOW() - run_at), 0)::FLOAT8 AS waited
+        FROM claimed ORDER BY priority DESC, id",
+    )
+    .bind(is_async)
+    .bind(queues)
+    .bind(worker)
+    .bind(lease.as_secs_f64())
+    .bind(limit as i64)
+    .fetch_all(conn)
+    .await
 }
 
+/// Whether there are jobs `claim_jobs` would claim, with the same filters
+pub async fn has_claimable_jobs(
+    conn: impl Executor<'_, Database = Postgres>,
+    is_async: Option<bool>,
+    queues: Option<&[String]>,
+) -> Result<bool, sqlx::Error> {
+    let (exists,) = sqlx::query_as::<_, (bool,)>(
+        "SELECT EXISTS (
+            SELECT 1 FROM _background_tasks
+            WHERE ($1::BOOLEAN IS NULL OR is_async = $1) AND run_at <= NOW()
+                AND (locked_until IS NULL OR locked_until < NOW())
+                AND ($2::TEXT[] IS NULL OR queue = ANY($2))
+        )",
+    )
+    .bind(is_async)
+    .bind(queues)
+    .fetch_one(conn)
+    .await?;
+    Ok(exists)
+}
+
+/// Extend the leases on the jobs with `ids` until `lease` has passed, as long as they are still
+/// held with the matching `lease_tokens`
+pub async fn extend_leases(
+    conn: impl Executor<'_, Database = Postgres>,
+    ids: &[i64],
+    lease_tokens: &[String],
+    lease: Duration,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE _background_tasks SET locked_until = NOW() + make_interval(secs => $3)
+        FROM UNNEST($1::BIGINT[], $2::TEXT[]) AS leased (id, lease_token)
+        WHERE _background_tasks.id = leased.id
+            AND _background_tasks.lease_token = leased.lease_token",
+    )
+    .bind(ids)
+    .bind(lease_tokens)
+    .bind(lease.as_secs_f64())
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Delete a job which ran successfully.
+/// Returns `false` if the lease with `lease_token` isn't held on the job anymore.
 pub async fn delete_successful_job(
     conn: impl Executor<'_, Database = Postgres>,
     id: i64,
-) -> Result<(), sqlx::Error> {
-    sqlx::query("DELETE FROM _background_tasks WHERE id=$1")
+    lease_token: &str,
+) -> Result<bool, sqlx::Error> {
+    let done = sqlx::query("DELETE FROM _background_tasks WHERE id = $1 AND lease_token = $2")
         .bind(id)
+        .bind(lease_token)
         .execute(conn)
         .await?;
-    Ok(())
+    Ok(done.rows_affected() > 0)
 }
 
+/// Bump the retry counter of a failed job, store its error, and reschedule it to run once
+/// `retry_delay` has passed. Since `claim_jobs` skips jobs that aren't due yet,
+/// the job won't be picked up again until then.
+/// Returns `false` if the lease with `lease_token` isn't held on the job anymore.
 pub async fn update_failed_job(
     conn: impl Executor<'_, Database = Postgres>,
     id: i64,
-) -> Result<(), PerformError> {
-    sqlx::query(
-        "UPDATE _background_tasks SET retries = retries + 1, last_retry = NOW() WHERE id = $1",
+    lease_token: &str,
+    retry_delay: Duration,
+    error: &str,
+) -> Result<bool, sqlx::Error> {
+    let done = sqlx::query(
+        "UPDATE _background_tasks
+        SET retries = retries + 1, last_retry = NOW(), last_error = $4,
+            run_at = NOW() + make_interval(secs => $3),
+            locked_by = NULL, locked_until = NULL, lease_token = NULL
+        WHERE id = $1 AND lease_token = $2",
     )
     .bind(id)
+    .bind(lease_token)
+    .bind(retry_delay.as_secs_f64())
+    .bind(error)
+    .execute(conn)
+    .await?;
+    Ok(done.rows_affected() > 0)
+}
+
+/// A failed attempt at running a job, as recorded in the history table
+pub struct FailedAttempt<'a> {
+    pub task_id: i64,
+    pub job_type: &'a str,
+    /// 1 for the first attempt
+    pub attempt: i32,
+    pub error: Option<&'a str>,
+    pub panic: Option<&'a str>,
+    pub worker: &'a str,
+    pub duration: Duration,
+}
+
+pub async fn record_failed_attempt(
+    conn: impl Executor<'_, Database = Postgres>,
+    attempt: FailedAttempt<'_>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO _background_tasks_history
+            (task_id, job_type, attempt, error, panic, worker, duration)
+        VALUES ($1, $2, $3, $4, $5, $6, make_interval(secs => $7))",
+    )
+    .bind(attempt.task_id)
+    .bind(attempt.job_type)
+    .bind(attempt.attempt)
+    .bind(attempt.error)
+    .bind(attempt.panic)
+    .bind(attempt.worker)
+    .bind(attempt.duration.as_secs_f64())
     .execute(conn)
     .await?;
     Ok(())
 }
-/*
-pub async fn unlocked_tasks_count(conn: impl Executor<'_, Database = Postgres>, is_async: bool) -> Result<i64, EnqueueError> {
-    let count = sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM _background_tasks WHERE is_async = $1")
-        .bind(is_async)
+
+/// Move a job which ran out of retries to the dead letter table.
+/// Returns `false` if the lease with `lease_token` isn't held on the job anymore.
+pub async fn kill_job(
+    conn: impl Executor<'_, Database = Postgres>,
+    id: i64,
+    lease_token: &str,
+    error: &str,
+) -> Result<bool, sqlx::Error> {
+    let done = sqlx::query(
+        "WITH dead AS (DELETE FROM _background_tasks WHERE id = $1 AND lease_token = $2 RETURNING *)
+        INSERT INTO _background_tasks_dead (
+            id, job_type, is_async, priority, queue, data, retries, error, created_at,
+            is_unique, trace_context
+        )
+        SELECT id, job_type, is_async, priority, queue, data, retries + 1, $3, created_at,
+            is_unique, trace_context
+        FROM dead",
+    )
+    .bind(id)
+    .bind(lease_token)
+    .bind(error)
+    .execute(conn)
+    .await?;
+    Ok(done.rows_affected() > 0)
+}
+
+/// A job that ran out of retries
+#[derive(Debug, Clone)]
+pub struct DeadJob {
+    /// ID the job had in the queue
+    pub id: i64,
+    /// Type of the job
+    pub job_type: String,
+    /// MessagePack encoded job data
+    pub data: Vec<u8>,
+    /// Whether the job is run with `perform_async`
+    pub is_async: bool,
+    /// Priority the job was enqueued with
+    pub priority: i32,
+    /// Queue the job was enqueued on
+    pub queue: String,
+    /// How many times the job failed
+    pub retries: i32,
+    /// The error of the final attempt
+    pub error: String,
+    /// When the job was first enqueued
+    pub created_at: SystemTime,
+    /// When the job ran out of retries
+    pub died_at: SystemTime,
+}
+
+type DeadJobRow = (
+    i64,
+    String,
+    Vec<u8>,
+    bool,
+    i32,
+    String,
+    i32,
+    String,
+    f64,
+    f64,
+);
+
+impl From<DeadJobRow> for DeadJob {
+    fn from(row: DeadJobRow) -> Self {
+        let (id, job_type, data, is_async, priority, queue, retries, error, created_at, died_at) =
+            row;
+        Self {
+            id,
+            job_type,
+            data,
+            is_async,
+            priority,
+            queue,
+            retries,
+            error,
+            created_at: from_epoch(created_at),
+            died_at: from_epoch(died_at),
+        }
+    }
+}
+
+pub(crate) fn from_epoch(secs: f64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs_f64(secs.max(0.0))
+}
+
+const SELECT_DEAD_JOBS: &str =
+    "SELECT id, job_type, data, is_async, priority, queue, retries, error,
+        EXTRACT(EPOCH FROM created_at::timestamptz)::FLOAT8,
+        EXTRACT(EPOCH FROM died_at::timestamptz)::FLOAT8
+    FROM _background_tasks_dead";
+
+/// List the jobs that ran out of retries, most recently killed first
+pub async fn dead_jobs(
+    conn: impl Executor<'_, Database = Postgres>,
+) -> Result<Vec<DeadJob>, Error> {
+    let sql = format!("{} ORDER BY died_at DESC, id", SELECT_DEAD_JOBS);
+    let jobs = sqlx::query_as::<_, DeadJobRow>(&sql)
+        .fetch_all(conn)
+        .await?;
+    Ok(jobs.into_iter().map(DeadJob::from).collect())
+}
+
+/// Get the job with ID `id` that ran out of retries
+pub async fn get_dead_job(
+    conn: impl Executor<'_, Database = Postgres>,
+    id: i64,
+) -> Result<Option<DeadJob>, Error> {
+    let sql = format!("{} WHERE id = $1", SELECT_DEAD_JOBS);
+    let job = sqlx::query_as::<_, DeadJobRow>(&sql)
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+    Ok(job.map(DeadJob::from))
+}
+
+/// Move a dead job back into the queue with its retry counter reset.
+/// A unique job is dropped instead if an identical job is already queued,
+/// like enqueueing it again would be.
+/// Returns `false` if there was no dead job with this ID.
+pub async fn resurrect_dead_job(
+    conn: impl Executor<'_, Database = Postgres>,
+    id: i64,
+) -> Result<bool, Error> {
+    let sql = format!(
+        "WITH dead AS (DELETE FROM _background_tasks_dead WHERE id = $1 RETURNING *),
+        {}
+        SELECT COUNT(*) FROM dead",
+        RESURRECT_DEAD
+    );
+    let (resurrected,) = sqlx::query_as::<_, (i64,)>(&sql)
+        .bind(id)
         .fetch_one(conn)
         .await?;
-    Ok(count.0)
+    Ok(resurrected > 0)
+}
+
+/// Put the rows of a `dead` CTE, deleted from the dead letter table, back in the queue
+pub(crate) const RESURRECT_DEAD: &str = "resurrected AS (
+    INSERT INTO _background_tasks (
+        id, job_type, is_async, priority, queue, data, created_at, is_unique, trace_context
+    )
+    SELECT id, job_type, is_async, priority, queue, data, created_at, is_unique, trace_context
+    FROM dead
+    ON CONFLICT (job_type, md5(data)) WHERE is_unique DO NOTHING
+)";
+
+/// Delete every dead job. Returns how many jobs were deleted.
+pub async fn purge_dead_jobs(conn: impl Executor<'_, Database = Postgres>) -> Result<u64, Error> {
+    let done = sqlx::query("DELETE FROM _background_tasks_dead")
+        .execute(conn)
+        .await?;
+    Ok(done.rows_affected())
 }
-*/
+
+/// How many jobs are waiting to be run on a queue, and for how long the oldest one has waited
+#[cfg(feature = "metrics")]
+pub struct QueueDepth {
+    pub queue: String,
+    pub pending: i64,
+    /// Seconds since the oldest pending job became due
+    pub oldest_age: f64,
+}
+
+/// The jobs which are due to run and aren't leased to a worker, per queue
+#[cfg(feature = "metrics")]
+pub async fn queue_depths(
+    conn: impl Executor<'_, Database = Postgres>,
+) -> Result<Vec<QueueDepth>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, (String, i64, f64)>(
+        "SELECT queue, COUNT(*), GREATEST(EXTRACT(EPOCH FROM NOW() - MIN(run_at)), 0)::FLOAT8
+        FROM _background_tasks
+        WHERE run_at <= NOW() AND (locked_until IS NULL OR locked_until < NOW())
+        GROUP BY queue ORDER BY queue",
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(queue, pending, oldest_age)| QueueDepth {
+            queue,
+            pending,
+            oldest_age,
+        })
+        .collect())
+}
+
 /// Gets jobs which failed
 #[cfg(any(test, feature = "test_components"))]
 pub async fn failed_job_count(
@@ -36,6 +36,9 @@ pub enum Error {
     /// Error occuring as a result of trying to spawn a future onto an executor
     #[error("Couldn't spawn onto executor {0}")]
     Spawn(#[from] futures::task::SpawnError),
+    /// Error spawning the thread which extends the leases of running jobs
+    #[error("Couldn't spawn the heartbeat thread {0}")]
+    Heartbeat(std::io::Error),
     /// Error occured while trying to run the migrations for coil
     #[error("Migrations could not be run {0}")]
     Migrate(#[from] sqlx::migrate::MigrateError),
@@ -43,14 +46,47 @@ pub enum Error {
 
 #[derive(Debug, Error)]
 pub enum FetchError {
-    #[error("Got no response from worker")]
-    NoMessage,
     #[error("Timeout reached while waiting for worker to finish")]
     Timeout,
     #[error("Couldn't load job from storage {0}")]
     FailedLoadingJob(#[from] sqlx::Error),
 }
 
+/// Error running a claimed job, or recording its outcome once it has run.
+/// The job stays in the queue, and is run again once its lease has expired.
+#[derive(Debug, Error)]
+pub enum FinishError {
+    /// Error executing SQL, after retrying
+    #[error("Couldn't record the outcome of job {job_id}: {source}")]
+    Sql {
+        job_id: i64,
+        #[source]
+        source: sqlx::Error,
+    },
+    /// The runner's lease on the job expired and another worker claimed it,
+    /// or the job was deleted, before its outcome could be recorded
+    #[error("Job {0} was lost before its outcome could be recorded")]
+    Lost(i64),
+    /// The executor refused to run the async job
+    #[error("Couldn't spawn job {job_id}: {source}")]
+    Spawn {
+        job_id: i64,
+        #[source]
+        source: futures::task::SpawnError,
+    },
+}
+
+impl FinishError {
+    /// The ID of the job which couldn't be run or whose outcome couldn't be recorded
+    pub fn job_id(&self) -> i64 {
+        match self {
+            FinishError::Sql { job_id, .. } => *job_id,
+            FinishError::Lost(job_id) => *job_id,
+            FinishError::Spawn { job_id, .. } => *job_id,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum EnqueueError {
     /// An error occurred while trying to insert the task into Postgres
@@ -0,0 +1,124 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of coil.
+
+// coil is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// coil is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with coil.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hooks a runner calls as it runs jobs, and what they are told about the jobs
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An attempt at running a job which just started
+#[derive(Debug, Clone)]
+pub struct JobStart {
+    /// ID of the job
+    pub id: i64,
+    /// Type of the job
+    pub job_type: String,
+    /// Queue the job was enqueued on
+    pub queue: String,
+    /// 1 for the first attempt
+    pub attempt: u32,
+    /// How long the job waited to be picked up after it became due
+    pub queued_for: Duration,
+}
+
+/// An attempt at running a job which finished
+#[derive(Debug, Clone)]
+pub struct JobOutcome {
+    /// ID of the job
+    pub id: i64,
+    /// Type of the job
+    pub job_type: String,
+    /// Queue the job was enqueued on
+    pub queue: String,
+    /// 1 for the first attempt
+    pub attempt: u32,
+    /// How long the job waited to be picked up after it became due
+    pub queued_for: Duration,
+    /// How long the attempt ran for
+    pub duration: Duration,
+    /// Why the attempt failed, or `None` if it succeeded
+    pub failure: Option<JobFailure>,
+    /// Whether the outcome was recorded. If it wasn't, the job stays in the queue
+    /// and is run again once its lease has expired, and `on_error` is told why.
+    pub recorded: bool,
+}
+
+impl JobOutcome {
+    /// Whether the attempt succeeded
+    pub fn is_success(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Why an attempt at running a job failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobFailure {
+    /// The job returned an error
+    Error(String),
+    /// The job panicked
+    Panic(String),
+    /// The job ran for longer than its timeout
+    Timeout(Duration),
+}
+
+impl fmt::Display for JobFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobFailure::Error(message) | JobFailure::Panic(message) => f.write_str(message),
+            JobFailure::Timeout(timeout) => write!(f, "job timed out after {:?}", timeout),
+        }
+    }
+}
+
+type Hook<T> = Option<Arc<dyn Fn(&T) + Send + Sync + 'static>>;
+
+/// The hooks a runner was built with
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub on_start: Hook<JobStart>,
+    pub on_finish: Hook<JobOutcome>,
+    pub on_failure: Hook<JobOutcome>,
+    pub on_retry_exhausted: Hook<JobOutcome>,
+}
+
+impl Hooks {
+    pub fn started(&self, start: &JobStart) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::started(start);
+        if let Some(f) = &self.on_start {
+            f(start)
+        }
+    }
+
+    /// Call the hooks for an attempt which finished.
+    /// `on_finish` is only called if the outcome was recorded, last, once the other hooks have run.
+    pub fn finished(&self, outcome: &JobOutcome, retry_exhausted: bool) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::finished(outcome, retry_exhausted);
+        if !outcome.is_success() {
+            if let Some(f) = &self.on_failure {
+                f(outcome)
+            }
+            if let (true, Some(f)) = (retry_exhausted, &self.on_retry_exhausted) {
+                f(outcome)
+            }
+        }
+        if let (true, Some(f)) = (outcome.recorded, &self.on_finish) {
+            f(outcome)
+        }
+    }
+}
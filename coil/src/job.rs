@@ -14,10 +14,26 @@
 // You should have received a copy of the GNU General Public License
 // along with coil.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::backoff::Backoff;
+use crate::db::RunAt;
 use crate::error::{EnqueueError, PerformError};
 use serde::{de::DeserializeOwned, Serialize};
 use sqlx::{Executor, Postgres};
+use std::borrow::Cow;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The stable name an instantiation of a generic job is stored under, like `string` for
+/// `resize_image::Job<String>`. Generated for each entry of the `register(String = "string")`
+/// option. The name has to stay the same for jobs already in the queue to be run.
+pub trait JobInstantiation {
+    const NAME: &'static str;
+
+    /// Whether this instantiation runs the jobs enqueued as the bare `JOB_TYPE`, before
+    /// instantiations got their own job types. Set for the first entry of `register(...)`.
+    /// At most one instantiation of a job should set it.
+    const LEGACY: bool = false;
+}
 
 /// Background job
 #[async_trait::async_trait]
@@ -28,20 +44,103 @@ pub trait Job: Serialize + DeserializeOwned {
     ///  any configuration, and any other static data or shared resources.
     type Environment: 'static + Send + Sync;
 
-    /// The key to use for storing this job.
+    /// The key to use for storing this job, see `job_type`.
     /// Typically this is the name of your struct in `snake_case`.
+    /// Jobs already in the queue are only run while a registered job has their type as its
+    /// `JOB_TYPE` or one of its `ALIASES`, so keep the old type as an alias when changing it.
     const JOB_TYPE: &'static str;
-    #[doc(hidden)]
+
+    /// Other job types this job is also run for, like the names it was stored under before it
+    /// was renamed. Jobs are always enqueued as `JOB_TYPE`.
+    /// Like `JOB_TYPE`, the aliases of generic jobs are followed by their `type_parameters`.
+    const ALIASES: &'static [&'static str] = &[];
+
+    /// The type parameters of a generic job, like `<string>`.
+    /// `#[coil::background_job]` and `#[derive(coil::Job)]` implement this for generic jobs with
+    /// the `JobInstantiation::NAME` given to each instantiation in `register(...)`.
+    ///
+    /// Jobs enqueued before instantiations got their own job types are stored as the bare
+    /// `JOB_TYPE`. They are still run, by the instantiation for which `runs_legacy_job_type`
+    /// is true: the first one listed in `register(...)`, so list the one they were enqueued as
+    /// first until they have drained.
+    fn type_parameters() -> Option<String> {
+        None
+    }
+
+    /// Whether this instantiation of a generic job also runs the jobs stored as the bare
+    /// `JOB_TYPE` and `ALIASES`, see `JobInstantiation::LEGACY`
+    fn runs_legacy_job_type() -> bool {
+        false
+    }
+
+    /// The job type this job is stored with.
+    /// This is `JOB_TYPE`, followed by the `type_parameters` of generic jobs, so that each
+    /// instantiation of a generic job is run as the type it was enqueued as.
+    fn job_type() -> Cow<'static, str> {
+        match Self::type_parameters() {
+            Some(params) => Cow::Owned(format!("{}{}", Self::JOB_TYPE, params)),
+            None => Cow::Borrowed(Self::JOB_TYPE),
+        }
+    }
 
     /// Marker for whether this trait should be executed with `perform_async`
+    #[doc(hidden)]
     const ASYNC: bool;
 
+    /// The priority this job is enqueued with, unless overridden with `enqueue_with_priority`.
+    /// Jobs with a higher priority are run before jobs with a lower priority.
+    const PRIORITY: i32 = 0;
+
+    /// The queue this job is enqueued on.
+    /// Runners built with `Builder::queues` only run jobs from the queues they serve.
+    const QUEUE: &'static str = "default";
+
+    /// How long to wait before retrying this job after it fails.
+    /// `None` uses the backoff the runner was built with.
+    const BACKOFF: Option<Backoff> = None;
+
+    /// How many times this job is retried before it is moved to the dead letter table.
+    /// `None` uses the maximum the runner was built with.
+    const MAX_RETRIES: Option<u32> = None;
+
+    /// How long this job may run before the attempt is failed as timed out.
+    /// `None` uses the timeout the runner was built with.
+    const TIMEOUT: Option<Duration> = None;
+
+    /// Whether enqueueing this job is skipped while a job of the same type with the same
+    /// arguments is still in the queue, whether it is waiting, running or waiting to be retried.
+    const UNIQUE: bool = false;
+
     /// inserts the job into the Postgres Database
     async fn enqueue<'a, C>(self, conn: C) -> Result<(), EnqueueError>
     where
         C: Executor<'a, Database = Postgres>,
     {
-        crate::db::enqueue_job(conn, self).await
+        crate::db::enqueue_job(conn, self, Self::PRIORITY, RunAt::Now).await
+    }
+
+    /// inserts the job into the Postgres Database with a priority other than `PRIORITY`
+    async fn enqueue_with_priority<'a, C>(self, priority: i32, conn: C) -> Result<(), EnqueueError>
+    where
+        C: Executor<'a, Database = Postgres>,
+    {
+        crate::db::enqueue_job(conn, self, priority, RunAt::Now).await
+    }
+
+    /// inserts the job into the Postgres Database, to be run no earlier than `time`
+    async fn enqueue_at<'a, C>(self, time: SystemTime, conn: C) -> Result<(), EnqueueError>
+    where
+        C: Executor<'a, Database = Postgres>,
+    {
+        crate::db::enqueue_job(conn, self, Self::PRIORITY, RunAt::At(time)).await
+    }
+
+    /// inserts the job into the Postgres Database, to be run once `delay` has passed
+    async fn enqueue_in<'a, C>(self, delay: Duration, conn: C) -> Result<(), EnqueueError>
+    where
+        C: Executor<'a, Database = Postgres>,
+    {
+        crate::db::enqueue_job(conn, self, Self::PRIORITY, RunAt::In(delay)).await
     }
 
     /// Logic for running a synchronous job
@@ -61,6 +160,50 @@ pub trait Job: Serialize + DeserializeOwned {
     }
 }
 
+/// What a synchronous job deriving `coil::Job` does when it is run.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize, coil::Job)]
+/// #[coil(env = Environment, queue = "reports")]
+/// struct RenderReport {
+///     id: i64,
+/// }
+///
+/// impl coil::Perform for RenderReport {
+///     fn perform(self, env: &Environment, pool: &sqlx::PgPool) -> Result<(), PerformError> {
+///         env.renderer.render(self.id)
+///     }
+/// }
+/// ```
+pub trait Perform: Job {
+    fn perform(self, env: &Self::Environment, pool: &sqlx::PgPool) -> Result<(), PerformError>;
+}
+
+/// What an asynchronous job deriving `coil::Job` with `#[coil(async)]` does when it is run.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize, coil::Job)]
+/// #[coil(env = Environment, async)]
+/// struct SendInvoice {
+///     customer: i64,
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl coil::PerformAsync for SendInvoice {
+///     async fn perform(self, env: Arc<Environment>, pool: &sqlx::PgPool) -> Result<(), PerformError> {
+///         env.mailer.send_invoice(self.customer).await
+///     }
+/// }
+/// ```
+#[async_trait::async_trait]
+pub trait PerformAsync: Job + Send {
+    async fn perform(
+        self,
+        env: Arc<Self::Environment>,
+        pool: &sqlx::PgPool,
+    ) -> Result<(), PerformError>;
+}
+
 #[async_trait::async_trait]
 pub trait JobExt: Job {
     async fn enqueue_batch(data: Vec<Self>, conn: &mut sqlx::PgConnection) -> Result<(), EnqueueError>
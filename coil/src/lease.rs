@@ -0,0 +1,79 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of coil.
+
+// coil is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// coil is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with coil.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Keeping the leases on the jobs a runner is running alive
+
+use crate::db;
+use crate::shutdown::InFlight;
+use futures::executor::block_on;
+use sqlx::PgPool;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// How many times a lease is extended before it would expire
+const HEARTBEATS_PER_LEASE: u32 = 3;
+
+/// One claim on a job. A job claimed again after its lease expired gets a new token,
+/// which tells the attempts at it apart even when they run in the same worker.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Lease {
+    pub job_id: i64,
+    pub token: String,
+}
+
+impl Lease {
+    pub fn of(job: &db::BackgroundJob) -> Self {
+        Self {
+            job_id: job.id,
+            token: job.lease_token.clone(),
+        }
+    }
+}
+
+/// Spawn a thread which extends the leases on the jobs in `in_flight` until they're all
+/// committed and the runner they belong to is gone.
+pub(crate) fn spawn_heartbeat(
+    pg_pool: PgPool,
+    lease: Duration,
+    in_flight: &Arc<InFlight>,
+) -> std::io::Result<()> {
+    let in_flight = Arc::downgrade(in_flight);
+    thread::Builder::new()
+        .name("coil-heartbeat".into())
+        .spawn(move || heartbeat(pg_pool, lease, in_flight))?;
+    Ok(())
+}
+
+fn heartbeat(pg_pool: PgPool, lease: Duration, in_flight: Weak<InFlight>) {
+    loop {
+        thread::sleep(lease / HEARTBEATS_PER_LEASE);
+        let running = match in_flight.upgrade() {
+            Some(in_flight) => in_flight.running(),
+            None => return,
+        };
+        if running.is_empty() {
+            continue;
+        }
+        let (ids, tokens): (Vec<_>, Vec<_>) = running
+            .into_iter()
+            .map(|lease| (lease.job_id, lease.token))
+            .unzip();
+        if let Err(e) = block_on(db::extend_leases(&pg_pool, &ids, &tokens, lease)) {
+            log::warn!("Failed to extend the leases of jobs {:?}: {}", ids, e);
+        }
+    }
+}
@@ -21,12 +21,34 @@
 //! - Queue functions with generics
 //! - SQL queries in `coil` are ran asynchronously wherever possible
 //! - Migrations are stored in the binary, and accessible via a `migrate()` fn. No more needing to copy-paste migration files!
+//! - Failed jobs are retried with a configurable [`Backoff`]
+//! - Jobs can be split into named queues, each served by its own runners
+//! - `Runner::run_forever` keeps a runner going, woken up by Postgres as soon as a job is enqueued
+//! - Jobs can be given a timeout, after which they are failed and retried
+//! - Jobs are leased to the runner running them, and run again if that runner dies
+//! - Middleware can wrap every job a runner performs, for concerns shared by all of them
+//! - With the `tracing` feature, each attempt at a job runs in a span continuing the trace the job was enqueued from
+//! - With the `metrics` feature, `render_metrics` renders counters, histograms and queue gauges for Prometheus
+//! - Jobs can be listed, counted, retried and deleted with the `admin` module, without writing SQL
+//! - Hooks are told when jobs start, and how each attempt went: its outcome, time spent queued and running
+//! - Jobs can be types of your own, with `#[derive(coil::Job)]` and a hand-written [`Perform`] or [`PerformAsync`] impl
 
+pub mod admin;
+mod backoff;
 mod db;
 mod error;
+mod hooks;
 mod job;
+mod lease;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod middleware;
 mod registry;
 mod runner;
+mod shutdown;
+mod slots;
+mod timeout;
+mod trace;
 mod batch;
 
 #[doc(hidden)]
@@ -43,12 +65,21 @@ pub use serde::{de::DeserializeOwned, Deserialize, Serialize};
 #[doc(hidden)]
 pub use registry::JobVTable;
 
-pub use crate::db::migrate;
+pub use crate::backoff::Backoff;
+pub use crate::db::{dead_jobs, migrate, purge_dead_jobs, resurrect_dead_job, DeadJob};
 pub use crate::error::*;
+pub use crate::hooks::{JobFailure, JobOutcome, JobStart};
 pub use crate::job::*;
+#[cfg(feature = "metrics")]
+pub use crate::metrics::render_metrics;
+pub use crate::middleware::{JobMiddleware, NextAsync, NextSync};
 #[cfg(any(test, feature = "test_components"))]
 pub use crate::runner::Event;
 pub use crate::runner::{Builder, Runner};
+pub use crate::shutdown::ShutdownHandle;
+pub use crate::timeout::is_cancelled;
+#[cfg(feature = "tracing")]
+pub use crate::trace::{set_propagator, Propagator};
 pub use coil_proc_macro::*;
 
 #[cfg(test)]
@@ -0,0 +1,238 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of coil.
+
+// coil is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// coil is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with coil.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Counters and histograms about the jobs this process enqueued and ran,
+//! rendered in the Prometheus text exposition format along with gauges about the queues
+
+use crate::db;
+use crate::error::Error;
+use crate::hooks::{JobOutcome, JobStart};
+use sqlx::{Executor, Postgres};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the buckets durations are counted in, in seconds
+const BUCKETS: [f64; 14] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+const ENQUEUED: &str = "coil_jobs_enqueued_total";
+const STARTED: &str = "coil_jobs_started_total";
+const SUCCEEDED: &str = "coil_jobs_succeeded_total";
+const FAILED: &str = "coil_jobs_failed_total";
+const DEAD: &str = "coil_jobs_dead_total";
+const DURATION: &str = "coil_job_duration_seconds";
+const QUEUE_LATENCY: &str = "coil_job_queue_latency_seconds";
+
+const COUNTERS: [(&str, &str); 5] = [
+    (ENQUEUED, "Jobs enqueued"),
+    (STARTED, "Attempts at running jobs which started"),
+    (SUCCEEDED, "Attempts at running jobs which succeeded"),
+    (FAILED, "Attempts at running jobs which failed"),
+    (DEAD, "Jobs which ran out of retries"),
+];
+
+const HISTOGRAMS: [(&str, &str); 2] = [
+    (DURATION, "How long attempts at running jobs ran for"),
+    (
+        QUEUE_LATENCY,
+        "How long jobs waited to be picked up after they became due",
+    ),
+];
+
+/// Metrics recorded by this process, keyed by metric name and job type
+struct Registry {
+    counters: BTreeMap<(&'static str, String), u64>,
+    histograms: BTreeMap<(&'static str, String), Histogram>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    counters: BTreeMap::new(),
+    histograms: BTreeMap::new(),
+});
+
+#[derive(Default)]
+struct Histogram {
+    /// How many observations fell in each of `BUCKETS`, and not in an earlier one
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let value = value.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|&le| value <= le) {
+            self.buckets[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+fn with_registry(f: impl FnOnce(&mut Registry)) {
+    f(&mut REGISTRY.lock().expect("lock poisoned"))
+}
+
+fn count(registry: &mut Registry, metric: &'static str, job_type: &str, n: u64) {
+    *registry
+        .counters
+        .entry((metric, job_type.to_string()))
+        .or_default() += n;
+}
+
+fn observe(registry: &mut Registry, metric: &'static str, job_type: &str, value: Duration) {
+    registry
+        .histograms
+        .entry((metric, job_type.to_string()))
+        .or_default()
+        .observe(value);
+}
+
+/// `n` jobs of `job_type` were enqueued
+pub(crate) fn enqueued(job_type: &str, n: u64) {
+    with_registry(|r| count(r, ENQUEUED, job_type, n))
+}
+
+/// An attempt at running a job started
+pub(crate) fn started(start: &JobStart) {
+    with_registry(|r| {
+        count(r, STARTED, &start.job_type, 1);
+        observe(r, QUEUE_LATENCY, &start.job_type, start.queued_for);
+    })
+}
+
+/// An attempt at running a job finished, whether or not its outcome was recorded
+pub(crate) fn finished(outcome: &JobOutcome, retry_exhausted: bool) {
+    with_registry(|r| {
+        let job_type = &outcome.job_type;
+        if outcome.is_success() {
+            count(r, SUCCEEDED, job_type, 1);
+        } else {
+            count(r, FAILED, job_type, 1);
+            if retry_exhausted {
+                count(r, DEAD, job_type, 1);
+            }
+        }
+        observe(r, DURATION, job_type, outcome.duration);
+    })
+}
+
+/// Render the metrics in the Prometheus text exposition format, to be served from an HTTP endpoint.
+///
+/// Counters and histograms cover the jobs enqueued and run by this process, per `job_type`.
+/// `coil_queue_depth` and `coil_queue_oldest_job_age_seconds` are queried from `conn`,
+/// and cover the jobs which are due and not running, per queue.
+pub async fn render_metrics(conn: impl Executor<'_, Database = Postgres>) -> Result<String, Error> {
+    let depths = db::queue_depths(conn).await?;
+    let mut out = String::new();
+    render_recorded(&mut out);
+
+    write_header(
+        &mut out,
+        "coil_queue_depth",
+        "gauge",
+        "Jobs which are due and not running",
+    );
+    for depth in &depths {
+        let queue = escape(&depth.queue);
+        let _ = writeln!(
+            out,
+            "coil_queue_depth{{queue=\"{}\"}} {}",
+            queue, depth.pending
+        );
+    }
+    write_header(
+        &mut out,
+        "coil_queue_oldest_job_age_seconds",
+        "gauge",
+        "How long the oldest job which is due and not running has been due for",
+    );
+    for depth in &depths {
+        let queue = escape(&depth.queue);
+        let _ = writeln!(
+            out,
+            "coil_queue_oldest_job_age_seconds{{queue=\"{}\"}} {}",
+            queue, depth.oldest_age
+        );
+    }
+    Ok(out)
+}
+
+fn render_recorded(out: &mut String) {
+    let registry = REGISTRY.lock().expect("lock poisoned");
+    for &(metric, help) in &COUNTERS {
+        write_header(out, metric, "counter", help);
+        for ((_, job_type), value) in registry.counters.iter().filter(|((m, _), _)| *m == metric) {
+            let _ = writeln!(
+                out,
+                "{}{{job_type=\"{}\"}} {}",
+                metric,
+                escape(job_type),
+                value
+            );
+        }
+    }
+    for &(metric, help) in &HISTOGRAMS {
+        write_header(out, metric, "histogram", help);
+        for ((_, job_type), histogram) in registry
+            .histograms
+            .iter()
+            .filter(|((m, _), _)| *m == metric)
+        {
+            let job_type = escape(job_type);
+            let mut cumulative = 0;
+            for (le, n) in BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += n;
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{job_type=\"{}\",le=\"{}\"}} {}",
+                    metric, job_type, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{job_type=\"{}\",le=\"+Inf\"}} {}",
+                metric, job_type, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "{}_sum{{job_type=\"{}\"}} {}",
+                metric, job_type, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "{}_count{{job_type=\"{}\"}} {}",
+                metric, job_type, histogram.count
+            );
+        }
+    }
+}
+
+fn write_header(out: &mut String, metric: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", metric, help);
+    let _ = writeln!(out, "# TYPE {} {}", metric, kind);
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
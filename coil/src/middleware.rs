@@ -0,0 +1,168 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of coil.
+
+// coil is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// coil is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with coil.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Middleware wrapped around every job a runner performs
+
+use crate::error::PerformError;
+use crate::hooks::JobStart;
+use futures::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+type PerformFuture<'a> = Pin<Box<dyn Future<Output = Result<(), PerformError>> + Send + 'a>>;
+
+/// Code run around every job a runner performs, for concerns shared by all jobs
+/// like tracing, metrics, panic reporting or gating jobs behind feature flags.
+///
+/// Middleware is registered with `Builder::middleware`. The first one registered
+/// is the outermost: it runs first, and calls into the next one with `next.run()`.
+/// The last one calls into the job itself.
+/// Returning without running `next` skips the job, which is recorded with whatever was returned.
+///
+/// Both methods default to running `next` as is,
+/// so middleware only implements the ones for the kinds of job it cares about.
+///
+/// # Example
+/// ```ignore
+/// struct Timed;
+///
+/// impl<Env> coil::JobMiddleware<Env> for Timed {
+///     fn perform_sync(&self, job: &JobStart, _: &Env, next: NextSync<'_, Env>) -> Result<(), PerformError> {
+///         let started = Instant::now();
+///         let res = next.run();
+///         log::info!("{} took {:?}", job.job_type, started.elapsed());
+///         res
+///     }
+///
+///     fn perform_async<'a>(&'a self, job: &'a JobStart, _: &'a Arc<Env>, next: NextAsync<'a, Env>)
+///         -> Pin<Box<dyn Future<Output = Result<(), PerformError>> + Send + 'a>>
+///     {
+///         async move {
+///             let started = Instant::now();
+///             let res = next.run().await;
+///             log::info!("{} took {:?}", job.job_type, started.elapsed());
+///             res
+///         }.boxed()
+///     }
+/// }
+/// ```
+pub trait JobMiddleware<Env: 'static>: Send + Sync + 'static {
+    /// Wrap a synchronous job, running on the threadpool
+    fn perform_sync(
+        &self,
+        job: &JobStart,
+        env: &Env,
+        next: NextSync<'_, Env>,
+    ) -> Result<(), PerformError> {
+        let _ = (job, env);
+        next.run()
+    }
+
+    /// Wrap an asynchronous job, running on the executor
+    fn perform_async<'a>(
+        &'a self,
+        job: &'a JobStart,
+        env: &'a Arc<Env>,
+        next: NextAsync<'a, Env>,
+    ) -> PerformFuture<'a> {
+        let _ = (job, env);
+        next.run()
+    }
+}
+
+/// The rest of the middleware around a synchronous job, and the job itself
+pub struct NextSync<'a, Env> {
+    middleware: &'a [Box<dyn JobMiddleware<Env>>],
+    job: &'a JobStart,
+    env: &'a Env,
+    perform: Box<dyn FnOnce() -> Result<(), PerformError> + 'a>,
+}
+
+impl<'a, Env: 'static> NextSync<'a, Env> {
+    pub(crate) fn new(
+        middleware: &'a [Box<dyn JobMiddleware<Env>>],
+        job: &'a JobStart,
+        env: &'a Env,
+        perform: impl FnOnce() -> Result<(), PerformError> + 'a,
+    ) -> Self {
+        Self {
+            middleware,
+            job,
+            env,
+            perform: Box::new(perform),
+        }
+    }
+
+    /// Run the next middleware, or the job once there is none left
+    pub fn run(self) -> Result<(), PerformError> {
+        match self.middleware.split_first() {
+            Some((first, rest)) => {
+                let (job, env) = (self.job, self.env);
+                first.perform_sync(
+                    job,
+                    env,
+                    NextSync {
+                        middleware: rest,
+                        ..self
+                    },
+                )
+            }
+            None => (self.perform)(),
+        }
+    }
+}
+
+/// The rest of the middleware around an asynchronous job, and the job itself
+pub struct NextAsync<'a, Env> {
+    middleware: &'a [Box<dyn JobMiddleware<Env>>],
+    job: &'a JobStart,
+    env: &'a Arc<Env>,
+    perform: PerformFuture<'a>,
+}
+
+impl<'a, Env: 'static> NextAsync<'a, Env> {
+    pub(crate) fn new(
+        middleware: &'a [Box<dyn JobMiddleware<Env>>],
+        job: &'a JobStart,
+        env: &'a Arc<Env>,
+        perform: PerformFuture<'a>,
+    ) -> Self {
+        Self {
+            middleware,
+            job,
+            env,
+            perform,
+        }
+    }
+
+    /// Run the next middleware, or the job once there is none left
+    pub fn run(self) -> PerformFuture<'a> {
+        match self.middleware.split_first() {
+            Some((first, rest)) => {
+                let (job, env) = (self.job, self.env);
+                first.perform_async(
+                    job,
+                    env,
+                    NextAsync {
+                        middleware: rest,
+                        ..self
+                    },
+                )
+            }
+            None => self.perform,
+        }
+    }
+}
@@ -15,42 +15,47 @@
 
 #![allow(clippy::new_without_default)] // https://github.com/rust-lang/rust-clippy/issues/3632
 
+use crate::backoff::Backoff;
 use crate::error::PerformError;
 use crate::job::Job;
 use futures::{Future, FutureExt};
 use sqlx::PgPool;
 use std::any::{Any, TypeId};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Default)]
 #[allow(missing_debug_implementations)] // Can't derive debug
 /// A registry of background jobs, used to map job types to concrete perform
 /// functions at runtime.
 pub struct Registry<Env> {
-    jobs: HashMap<&'static str, JobVTable>,
+    jobs: HashMap<Cow<'static, str>, JobVTable>,
     _marker: PhantomData<Env>,
 }
 
 impl<Env: 'static> Registry<Env> {
     pub fn register_job<T: Job + 'static + Send>(&mut self) {
         if TypeId::of::<T::Environment>() == TypeId::of::<Env>() {
-            self.jobs.insert(T::JOB_TYPE, JobVTable::from_job::<T>());
+            insert_vtable(&mut self.jobs, JobVTable::from_job::<T>());
         } else {
-            log::warn!("could not register job {}", T::JOB_TYPE);
+            log::warn!("could not register job {}", T::job_type());
         }
     }
 
     /// Loads the registry from all invocations of [`register_job!`] for this
     /// environment type
     pub fn load() -> Self {
-        let jobs = inventory::iter::<JobVTable>
+        let mut jobs = HashMap::new();
+        let vtables = inventory::iter::<JobVTable>
             .into_iter()
-            .filter(|vtable| vtable.env_type == TypeId::of::<Env>())
-            .map(|&vtable| (vtable.job_type, vtable))
-            .collect();
+            .filter(|vtable| vtable.env_type == TypeId::of::<Env>());
+        for &vtable in vtables {
+            insert_vtable(&mut jobs, vtable);
+        }
 
         Self {
             jobs,
@@ -67,6 +72,22 @@ impl<Env: 'static> Registry<Env> {
     }
 }
 
+/// Run `vtable` for its job type and aliases, logging the job types another job
+/// was already registered as. The job registered last is the one which is run.
+fn insert_vtable(jobs: &mut HashMap<Cow<'static, str>, JobVTable>, vtable: JobVTable) {
+    for job_type in vtable.job_types() {
+        if let Some(other) = jobs.insert(job_type.clone(), vtable) {
+            if other.job != vtable.job {
+                log::error!(
+                    "More than one job is registered as {}, only one of them will be run. \
+                     Give the others a different `name`",
+                    job_type
+                );
+            }
+        }
+    }
+}
+
 /// Register a job to be run by coil. This must be called for any
 /// implementors of [`coil::Job`]
 #[macro_export]
@@ -88,7 +109,7 @@ enum SyncOrAsync {
     Async {
         fun: for<'a> fn(
             Vec<u8>,
-            Arc<(dyn Any + Send + Sync)>,
+            Arc<dyn Any + Send + Sync>,
             &'a PgPool,
         )
             -> Pin<Box<dyn Future<Output = Result<(), PerformError>> + Send + 'a>>,
@@ -98,8 +119,15 @@ enum SyncOrAsync {
 #[doc(hidden)]
 #[derive(Clone, Copy)]
 pub struct JobVTable {
+    job: TypeId,
     env_type: TypeId,
     job_type: &'static str,
+    aliases: &'static [&'static str],
+    type_parameters: fn() -> Option<String>,
+    legacy: bool,
+    backoff: Option<Backoff>,
+    max_retries: Option<u32>,
+    timeout: Option<Duration>,
     perform: SyncOrAsync,
 }
 
@@ -117,12 +145,42 @@ impl JobVTable {
             }
         };
         Self {
+            job: TypeId::of::<T>(),
             env_type: TypeId::of::<T::Environment>(),
             job_type: T::JOB_TYPE,
+            aliases: T::ALIASES,
+            type_parameters: T::type_parameters,
+            legacy: T::runs_legacy_job_type(),
+            backoff: T::BACKOFF,
+            max_retries: T::MAX_RETRIES,
+            timeout: T::TIMEOUT,
             perform,
         }
     }
 
+    /// The job type and every alias this job is run for, followed by the type parameters of
+    /// generic jobs
+    fn job_types(&self) -> impl Iterator<Item = Cow<'static, str>> {
+        let params = (self.type_parameters)();
+        std::iter::once(self.job_type)
+            .chain(self.aliases.iter().copied())
+            .map(move |job_type| match &params {
+                Some(params) => Cow::Owned(format!("{}{}", job_type, params)),
+                None => Cow::Borrowed(job_type),
+            })
+            .chain(self.legacy_job_types().map(Cow::Borrowed))
+    }
+
+    /// The bare job type and aliases of a generic job, which every instantiation of it was
+    /// stored as before each got its own job type. Only run by the instantiation marked as
+    /// `JobInstantiation::LEGACY`, the first one listed in `register(...)`.
+    fn legacy_job_types(&self) -> impl Iterator<Item = &'static str> {
+        let legacy = self.legacy && (self.type_parameters)().is_some();
+        std::iter::once(self.job_type)
+            .chain(self.aliases.iter().copied())
+            .filter(move |_| legacy)
+    }
+
     pub fn is_async(&self) -> bool {
         match self.perform {
             SyncOrAsync::Sync { .. } => false,
@@ -147,7 +205,7 @@ fn perform_sync_job<T: Job>(
 
 fn perform_async_job<'a, T: 'static + Job + Send>(
     data: Vec<u8>,
-    env: Arc<(dyn Any + Sync + Send)>,
+    env: Arc<dyn Any + Sync + Send>,
     conn: &'a PgPool,
 ) -> Pin<Box<dyn Future<Output = Result<(), PerformError>> + Send + 'a>> {
     async move {
@@ -172,6 +230,21 @@ pub struct PerformJob<Env> {
 }
 
 impl<Env: 'static + Send + Sync> PerformJob<Env> {
+    /// The backoff this job overrides the runner's with, if any
+    pub fn backoff(&self) -> Option<Backoff> {
+        self.vtable.backoff
+    }
+
+    /// The maximum number of retries this job overrides the runner's with, if any
+    pub fn max_retries(&self) -> Option<u32> {
+        self.vtable.max_retries
+    }
+
+    /// The timeout this job overrides the runner's with, if any
+    pub fn timeout(&self) -> Option<Duration> {
+        self.vtable.timeout
+    }
+
     /// Perform a job in a synchronous way.
     ///
     /// # Blocks
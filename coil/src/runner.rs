@@ -14,18 +14,25 @@
 // You should have received a copy of the GNU General Public License
 // along with coil.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::hooks::{Hooks, JobFailure, JobOutcome, JobStart};
 use crate::job::Job;
-use crate::{db, error::*, registry::Registry};
-use channel::Sender;
+use crate::lease::{self, Lease};
+use crate::middleware::{JobMiddleware, NextAsync, NextSync};
+use crate::shutdown::{InFlight, ShutdownHandle};
+use crate::slots::Slots;
+use crate::timeout;
+use crate::trace::AttemptSpan;
+use crate::{backoff::Backoff, db, error::*, registry::Registry};
 use futures::task::{Spawn, SpawnExt};
-use futures::{executor::block_on, future::FutureExt, Future, StreamExt};
+use futures::{executor::block_on, future::FutureExt, Future};
+use sqlx::postgres::PgListener;
 use sqlx::PgPool;
-use sqlx::Postgres;
 use std::any::Any;
-use std::panic::{catch_unwind, AssertUnwindSafe, PanicInfo, RefUnwindSafe, UnwindSafe};
+use std::panic::{catch_unwind, AssertUnwindSafe, RefUnwindSafe, UnwindSafe};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// Builder pattern struct for the Runner
 pub struct Builder<Env> {
@@ -35,9 +42,19 @@ pub struct Builder<Env> {
     executor: Arc<dyn Spawn>,
     max_tasks: Option<usize>,
     registry: Registry<Env>,
-    on_finish: Option<Arc<dyn Fn(i64) + Send + Sync + 'static>>,
+    middleware: Vec<Box<dyn JobMiddleware<Env>>>,
+    hooks: Hooks,
+    on_error: Option<Arc<dyn Fn(FinishError) + Send + Sync + 'static>>,
     /// Amount of time to wait until job is deemed a failure
     timeout: Option<Duration>,
+    backoff: Option<Backoff>,
+    max_retries: Option<u32>,
+    job_timeout: Option<Duration>,
+    worker_id: Option<String>,
+    keep_history: bool,
+    queues: Option<Vec<String>>,
+    poll_interval: Option<Duration>,
+    lease: Option<Duration>,
 }
 
 impl<Env: 'static> Builder<Env> {
@@ -50,16 +67,28 @@ impl<Env: 'static> Builder<Env> {
             max_tasks: None,
             num_threads: None,
             registry: Registry::load(),
-            on_finish: None,
+            middleware: Vec::new(),
+            hooks: Hooks::default(),
+            on_error: None,
             timeout: None,
+            backoff: None,
+            max_retries: None,
+            job_timeout: None,
+            worker_id: None,
+            keep_history: false,
+            queues: None,
+            poll_interval: None,
+            lease: None,
         }
     }
 
     ///  Register a job that hasn't or can't be registered by invoking the `register_job!` macro
     ///
-    /// Jobs that include generics must use this function in order to be registered with a runner.
-    /// Jobs must be registered with every generic that is used.
-    /// Jobs are available in the format `my_function_name::Job`.
+    /// Jobs that include generics must use this function in order to be registered with a runner,
+    /// unless their instantiations are listed in `register(...)`.
+    /// Jobs must be registered with every generic that is used, and each instantiation needs a
+    /// name to be stored and run as its own job type, see `JobInstantiation`.
+    /// Jobs are available in the format `my_function_name::Job`, as visible as the function.
     ///
     ///  # Example
     ///  ```ignore
@@ -80,22 +109,64 @@ impl<Env: 'static> Builder<Env> {
         self
     }
 
+    /// Wrap every job the runner performs in `middleware`.
+    /// Middleware is run in the order it was added, the first added being the outermost.
+    pub fn middleware(mut self, middleware: impl JobMiddleware<Env>) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
     /// specify the amount of threads to run the threadpool with
     pub fn num_threads(mut self, threads: usize) -> Self {
         self.num_threads = Some(threads);
         self
     }
 
-    /// Specify the maximum tasks  to queue in the threadpool at any given time
+    /// Specify the maximum number of tasks to run at any given time, sync and async ones together.
+    /// Defaults to the number of threads in the threadpool.
     pub fn max_tasks(mut self, max_tasks: usize) -> Self {
         self.max_tasks = Some(max_tasks);
         self
     }
 
-    /// Provide a hook that runs after a job has finished and all destructors have run
-    /// the `on_finish` closure accepts the job ID that finished as an argument
-    pub fn on_finish(mut self, on_finish: impl Fn(i64) + Send + Sync + 'static) -> Self {
-        self.on_finish = Some(Arc::new(on_finish));
+    /// Provide a hook that runs when a worker starts running a job
+    pub fn on_start(mut self, on_start: impl Fn(&JobStart) + Send + Sync + 'static) -> Self {
+        self.hooks.on_start = Some(Arc::new(on_start));
+        self
+    }
+
+    /// Provide a hook that runs after a job has finished, its outcome was recorded
+    /// and all destructors have run, whether the job succeeded or not.
+    /// It runs after `on_failure` and `on_retry_exhausted`.
+    pub fn on_finish(mut self, on_finish: impl Fn(&JobOutcome) + Send + Sync + 'static) -> Self {
+        self.hooks.on_finish = Some(Arc::new(on_finish));
+        self
+    }
+
+    /// Provide a hook that runs after a job has failed.
+    /// It runs even if the failure couldn't be recorded, see `JobOutcome::recorded`.
+    pub fn on_failure(mut self, on_failure: impl Fn(&JobOutcome) + Send + Sync + 'static) -> Self {
+        self.hooks.on_failure = Some(Arc::new(on_failure));
+        self
+    }
+
+    /// Provide a hook that runs after a job has failed for the last time.
+    /// The job was moved to the dead letter table, unless its failure couldn't be recorded,
+    /// see `JobOutcome::recorded`.
+    pub fn on_retry_exhausted(
+        mut self,
+        on_retry_exhausted: impl Fn(&JobOutcome) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.on_retry_exhausted = Some(Arc::new(on_retry_exhausted));
+        self
+    }
+
+    /// Provide a hook that runs when the outcome of a job couldn't be recorded, even after retrying.
+    /// The job stays in the queue, and is run again once its lease has expired.
+    /// `on_failure` and `on_retry_exhausted` still run after it if the job failed,
+    /// but `on_finish` doesn't.
+    pub fn on_error(mut self, on_error: impl Fn(FinishError) + Send + Sync + 'static) -> Self {
+        self.on_error = Some(Arc::new(on_error));
         self
     }
 
@@ -107,6 +178,77 @@ impl<Env: 'static> Builder<Env> {
         self
     }
 
+    /// Set how long failed jobs wait before they are retried.
+    /// Jobs may override this with `Job::BACKOFF`.
+    /// Defaults to `Backoff::default()`, an exponential backoff starting at one minute.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    /// Set how many times a failed job is retried before it is moved to the
+    /// `_background_tasks_dead` table. Jobs may override this with `Job::MAX_RETRIES`.
+    /// By default, failed jobs are retried forever.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Set how long a job may run before the attempt is failed as timed out.
+    /// Jobs may override this with `Job::TIMEOUT`. By default, jobs may run for as long as they like.
+    ///
+    /// Async jobs are dropped once they time out.
+    /// Sync jobs can't be interrupted, and should check [`crate::is_cancelled`] instead.
+    pub fn job_timeout(mut self, timeout: Duration) -> Self {
+        self.job_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the name this runner identifies itself with in the database.
+    /// Defaults to `hostname:pid`.
+    pub fn worker_id(mut self, worker_id: impl Into<String>) -> Self {
+        self.worker_id = Some(worker_id.into());
+        self
+    }
+
+    /// Record every failed attempt at running a job in `_background_tasks_history`,
+    /// along with the worker that ran it and how long it ran for.
+    /// The error of the latest failed attempt is always kept in `_background_tasks.last_error`.
+    pub fn keep_history(mut self, keep_history: bool) -> Self {
+        self.keep_history = keep_history;
+        self
+    }
+
+    /// Only run jobs enqueued on one of `queues`.
+    /// By default, a runner runs jobs from every queue.
+    ///
+    ///  # Example
+    ///  ```ignore
+    ///  RunnerBuilder::new(env, executor, conn)
+    ///      .queues(&["mailers", "default"])
+    ///  ```
+    pub fn queues(mut self, queues: &[&str]) -> Self {
+        self.queues = Some(queues.iter().map(|q| q.to_string()).collect());
+        self
+    }
+
+    /// Set how often `Runner::run_forever` checks for jobs when it hasn't been notified of any.
+    /// This is how long it takes at most for a scheduled or retried job to be picked up
+    /// once it is due. Defaults to 10 seconds.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = Some(poll_interval);
+        self
+    }
+
+    /// Set how long the jobs this runner claims are leased to it.
+    /// The runner extends the leases of the jobs it is running well before they expire.
+    /// If it dies, its jobs are run again by other runners once their lease has expired.
+    /// Defaults to 30 seconds.
+    pub fn lease(mut self, lease: Duration) -> Self {
+        self.lease = Some(lease);
+        self
+    }
+
     /// Build the runner
     pub fn build(self) -> Result<Runner<Env>, Error> {
         let threadpool = if let Some(t) = self.num_threads {
@@ -124,15 +266,40 @@ impl<Env: 'static> Builder<Env> {
         let timeout = self
             .timeout
             .unwrap_or_else(|| std::time::Duration::from_secs(5));
+        let worker_id = self.worker_id.unwrap_or_else(|| {
+            let host = hostname::get().unwrap_or_default();
+            format!("{}:{}", host.to_string_lossy(), std::process::id())
+        });
+        let worker_id: Arc<str> = worker_id.into();
+        let lease = self.lease.unwrap_or_else(|| Duration::from_secs(30));
+        let in_flight = Arc::new(InFlight::new());
+        lease::spawn_heartbeat(self.pg_pool.clone(), lease, &in_flight)
+            .map_err(Error::Heartbeat)?;
         Ok(Runner {
             threadpool,
             executor: self.executor,
             pg_pool: self.pg_pool,
             environment: Arc::new(self.environment),
             registry: Arc::new(self.registry),
-            max_tasks,
-            on_finish: self.on_finish,
+            middleware: self.middleware.into(),
+            slots: Slots::new(max_tasks),
+            async_turn: AtomicBool::new(false),
+            hooks: self.hooks,
+            on_error: self.on_error,
             timeout,
+            job_policy: JobPolicy {
+                backoff: self.backoff.unwrap_or_default(),
+                max_retries: self.max_retries,
+                timeout: self.job_timeout,
+            },
+            worker_id,
+            keep_history: self.keep_history,
+            queues: self.queues.map(Into::into),
+            poll_interval: self
+                .poll_interval
+                .unwrap_or_else(|| Duration::from_secs(10)),
+            lease,
+            in_flight,
         })
     }
 }
@@ -146,31 +313,211 @@ pub struct Runner<Env> {
     pg_pool: PgPool,
     environment: Arc<Env>,
     registry: Arc<Registry<Env>>,
-    /// maximum number of tasks to run at any one time
-    max_tasks: usize,
-    on_finish: Option<Arc<dyn Fn(i64) + Send + Sync + 'static>>,
+    middleware: Arc<[Box<dyn JobMiddleware<Env>>]>,
+    /// workers free to run jobs, `max_tasks` of them shared by sync and async jobs
+    slots: Slots,
+    /// whether async jobs get the odd free worker in the next round of `run_all_pending_tasks`
+    async_turn: AtomicBool,
+    hooks: Hooks,
+    on_error: Option<Arc<dyn Fn(FinishError) + Send + Sync + 'static>>,
     timeout: Duration,
+    job_policy: JobPolicy,
+    worker_id: Arc<str>,
+    keep_history: bool,
+    /// queues to run jobs from, or `None` for every queue
+    queues: Option<Arc<[String]>>,
+    poll_interval: Duration,
+    lease: Duration,
+    /// jobs that are running, and whether the runner is shutting down
+    in_flight: Arc<InFlight>,
+}
+
+/// The runner-wide defaults for running and retrying jobs
+#[derive(Copy, Clone)]
+struct JobPolicy {
+    backoff: Backoff,
+    max_retries: Option<u32>,
+    timeout: Option<Duration>,
+}
+
+impl JobPolicy {
+    /// What should happen to `job` if this attempt at running it fails
+    fn on_failure<Env: Send + Sync + 'static>(
+        &self,
+        registry: &Registry<Env>,
+        job: &db::BackgroundJob,
+    ) -> OnFailure {
+        let perform_fn = registry.get(&job.job_type);
+        let retries = job.retries.max(0) as u32;
+        let max_retries = perform_fn
+            .as_ref()
+            .and_then(|p| p.max_retries())
+            .or(self.max_retries);
+        if max_retries.map(|max| retries >= max).unwrap_or(false) {
+            OnFailure::Kill
+        } else {
+            let backoff = perform_fn.and_then(|p| p.backoff()).unwrap_or(self.backoff);
+            OnFailure::Retry(backoff.delay(retries))
+        }
+    }
+
+    /// How long `job` may run for
+    fn timeout<Env: Send + Sync + 'static>(
+        &self,
+        registry: &Registry<Env>,
+        job: &db::BackgroundJob,
+    ) -> Option<Duration> {
+        registry
+            .get(&job.job_type)
+            .and_then(|p| p.timeout())
+            .or(self.timeout)
+    }
+}
+
+/// What to do with a job whose attempt failed
+#[derive(Copy, Clone)]
+enum OnFailure {
+    /// Retry the job once the delay has passed
+    Retry(Duration),
+    /// The job is out of retries, move it to the dead letter table
+    Kill,
+}
+
+/// An attempt at running a job, whose outcome is recorded by `Bookkeeping::finish`
+struct Attempt {
+    /// The claim this attempt was started under
+    lease: Lease,
+    job_type: String,
+    queue: String,
+    /// How long the job waited to be claimed after it became due
+    queued_for: Duration,
+    /// How many times the job was retried before this attempt
+    retries: i32,
+    on_failure: OnFailure,
+    timeout: Option<Duration>,
+    started: Instant,
 }
 
-///
+impl Attempt {
+    fn start<Env: Send + Sync + 'static>(
+        job: &db::BackgroundJob,
+        registry: &Registry<Env>,
+        job_policy: &JobPolicy,
+    ) -> Self {
+        Self {
+            lease: Lease::of(job),
+            job_type: job.job_type.clone(),
+            queue: job.queue.clone(),
+            queued_for: Duration::from_secs_f64(job.waited.max(0.0)),
+            retries: job.retries,
+            on_failure: job_policy.on_failure(registry, job),
+            timeout: job_policy.timeout(registry, job),
+            started: Instant::now(),
+        }
+    }
+
+    /// What the `on_start` hook is told about this attempt
+    fn job_start(&self) -> JobStart {
+        JobStart {
+            id: self.lease.job_id,
+            job_type: self.job_type.clone(),
+            queue: self.queue.clone(),
+            attempt: self.attempt(),
+            queued_for: self.queued_for,
+        }
+    }
+
+    /// What the hooks called once this attempt finished are told about it
+    fn outcome(&self, failure: Option<&Failure>, recorded: bool) -> JobOutcome {
+        JobOutcome {
+            id: self.lease.job_id,
+            job_type: self.job_type.clone(),
+            queue: self.queue.clone(),
+            attempt: self.attempt(),
+            queued_for: self.queued_for,
+            duration: self.started.elapsed(),
+            failure: failure.map(Failure::to_job_failure),
+            recorded,
+        }
+    }
+
+    /// 1 for the first attempt at running the job
+    fn attempt(&self) -> u32 {
+        self.retries.max(0) as u32 + 1
+    }
+
+    /// When the attempt times out
+    fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|timeout| self.started + timeout)
+    }
+
+    /// The result of a sync job which returned `result`.
+    /// Sync jobs can't be interrupted, so one which returns after its deadline
+    /// has timed out, whatever it returned.
+    fn finished_sync(&self, result: Result<(), PerformError>) -> Result<(), Failure> {
+        match self.timeout {
+            Some(timeout) if self.started.elapsed() >= timeout => Err(Failure::Timeout(timeout)),
+            _ => result.map_err(Failure::Error),
+        }
+    }
+
+    /// Run an async job, catching its panics and dropping it once it times out
+    async fn run_async(
+        &self,
+        job: impl Future<Output = Result<(), PerformError>>,
+    ) -> Result<(), Failure> {
+        let job = AssertUnwindSafe(job).catch_unwind().map(|res| {
+            res.map_err(|e| Failure::Panic(try_to_extract_panic_info(&*e)))
+                .and_then(|r| r.map_err(Failure::Error))
+        });
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return job.await,
+        };
+        let job = job.fuse();
+        futures::pin_mut!(job);
+        futures::select! {
+            res = job => res,
+            _ = timer::Delay::new(timeout).fuse() => Err(Failure::Timeout(timeout)),
+        }
+    }
+}
+
+/// Why an attempt at running a job failed
+enum Failure {
+    /// The job returned an error
+    Error(PerformError),
+    /// The job panicked
+    Panic(PerformError),
+    /// The job ran for longer than its timeout
+    Timeout(Duration),
+}
+
+impl Failure {
+    fn message(&self) -> String {
+        match self {
+            Failure::Error(e) | Failure::Panic(e) => e.to_string(),
+            Failure::Timeout(timeout) => format!("job timed out after {:?}", timeout),
+        }
+    }
+
+    fn to_job_failure(&self) -> JobFailure {
+        match self {
+            Failure::Error(e) => JobFailure::Error(e.to_string()),
+            Failure::Panic(e) => JobFailure::Panic(e.to_string()),
+            Failure::Timeout(timeout) => JobFailure::Timeout(*timeout),
+        }
+    }
+}
+
+/// Events sent to the receivers tests wait on
+#[doc(hidden)]
+#[cfg(any(test, feature = "test_components"))]
 pub enum Event {
-    /// Queues are currently working
-    Working,
-    /// No more jobs available in queue
-    NoJobAvailable,
-    /// An error occurred loading the job from the database
-    ErrorLoadingJob(sqlx::Error),
     /// Test for waiting on dummy tasks
-    #[doc(hidden)]
-    #[cfg(any(test, feature = "test_components"))]
     Dummy,
 }
 
-type TxJobPair = Option<(
-    sqlx::Transaction<'static, sqlx::Postgres>,
-    db::BackgroundJob,
-)>;
-
 // Methods which don't require `RefUnwindSafe`
 impl<Env: 'static> Runner<Env> {
     /// Build the builder for `Runner`
@@ -188,6 +535,11 @@ impl<Env: 'static> Runner<Env> {
     pub fn connection_pool(&self) -> sqlx::PgPool {
         self.pg_pool.clone()
     }
+
+    /// Get a handle to gracefully shut the runner down with
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(Arc::clone(&self.in_flight))
+    }
 }
 
 impl<Env: Send + Sync + RefUnwindSafe + 'static> Runner<Env> {
@@ -195,217 +547,438 @@ impl<Env: Send + Sync + RefUnwindSafe + 'static> Runner<Env> {
     /// Spawns synchronous tasks onto a rayon threadpool
     /// Returns how many tasks were actually queued
     pub async fn run_all_sync_tasks(&self) -> Result<usize, FetchError> {
-        self.run_pending_tasks(|tx| self.run_single_sync_job(tx))
-            .await
+        self.run_pending_tasks(Some(false)).await
     }
 
     /// Run all asynchronous tasks
     /// Spawns asynchronous tasks onto the specified executor
     /// Returns how many tasks were actually queued
     pub async fn run_all_async_tasks(&self) -> Result<usize, FetchError> {
-        self.run_pending_tasks(|tx| self.run_single_async_job(tx))
-            .await
+        self.run_pending_tasks(Some(true)).await
     }
 
-    /// Runs all the pending tasks in a loop
-    /// Returns how many tasks are running as a result
-    async fn run_pending_tasks<F>(&self, fun: F) -> Result<usize, FetchError>
-    where
-        F: Fn(Sender<Event>),
-    {
-        let (tx, mut rx) = channel::bounded(self.max_tasks);
+    /// Run all sync and async tasks
+    /// Free workers are split evenly between sync and async tasks, so that neither kind
+    /// holds up the other while both are pending.
+    /// Returns how many tasks were actually queued
+    pub async fn run_all_pending_tasks(&self) -> Result<usize, FetchError> {
+        self.run_pending_tasks(None).await
+    }
 
-        let mut pending_messages = 0;
-        let mut queued = 0;
+    /// Keep running jobs as they are enqueued, until the runner is shut down with a
+    /// `ShutdownHandle`. Returns an error if the runner fails to start listening for new jobs.
+    ///
+    /// Instead of returning once the queue is empty like `run_all_pending_tasks`, the runner
+    /// waits for Postgres to notify it of a new job on one of the queues it serves.
+    /// It also checks for jobs every `poll_interval`, to pick up scheduled and retried jobs
+    /// once they are due.
+    pub async fn run_forever(&self) -> Result<(), Error> {
+        let mut listener = PgListener::connect_with(&self.pg_pool).await?;
+        listener.listen(db::NOTIFY_CHANNEL).await?;
+        while !self.in_flight.is_stopping() {
+            self.run_all_tasks().await;
+            self.wait_for_jobs(&mut listener).await;
+        }
+        Ok(())
+    }
+
+    /// Run all pending sync and async tasks, logging instead of returning errors
+    async fn run_all_tasks(&self) {
+        match self.run_all_pending_tasks().await {
+            Ok(_) => {}
+            // every worker is busy, we'll get to the remaining jobs on the next wakeup
+            Err(FetchError::Timeout) => log::debug!("Timed out waiting for a free worker"),
+            Err(e) => log::error!("Failed to fetch jobs: {}", e),
+        }
+    }
+
+    /// Wait until a job is enqueued on one of the queues this runner serves,
+    /// until `poll_interval` has passed, or until the runner is shut down
+    async fn wait_for_jobs(&self, listener: &mut PgListener) {
+        let mut poll = timer::Delay::new(self.poll_interval).fuse();
+        let stopping = self.in_flight.stopping().fuse();
+        futures::pin_mut!(stopping);
         loop {
-            let jobs_to_queue = if pending_messages == 0 {
-                self.max_tasks
-            } else {
-                self.max_tasks - pending_messages
+            let notification = listener.recv().fuse();
+            futures::pin_mut!(notification);
+            let notification = futures::select! {
+                n = notification => n,
+                _ = poll => return,
+                _ = stopping => return,
             };
-
-            for _ in 0..jobs_to_queue {
-                fun(tx.clone());
+            match notification {
+                Ok(n) if self.serves(n.payload()) => return,
+                Ok(_) => continue,
+                Err(e) => {
+                    log::error!("Failed to listen for new jobs: {}", e);
+                    break;
+                }
             }
+        }
+        futures::select! {
+            _ = poll => {},
+            _ = stopping => {},
+        }
+    }
 
-            pending_messages += jobs_to_queue;
+    /// Whether this runner runs jobs enqueued on `queue`
+    fn serves(&self, queue: &str) -> bool {
+        self.queues
+            .as_ref()
+            .map(|queues| queues.iter().any(|q| q == queue))
+            .unwrap_or(true)
+    }
 
-            let mut timeout = timer::Delay::new(self.timeout).fuse();
-            let mut next_msg = rx.next().fuse();
-            futures::select! {
-                msg = next_msg => {
-                    match msg {
-                        Some(Event::Working) => {
-                            pending_messages -= 1;
-                            queued += 1;
-                        },
-                        Some(Event::NoJobAvailable) => return Ok(queued),
-                        Some(Event::ErrorLoadingJob(e)) => return Err(FetchError::FailedLoadingJob(e)),
-                        None =>  return Err(FetchError::NoMessage.into()),
-                        _ => return Ok(queued),
-                    }
-                },
-                _ = timeout => return Err(FetchError::Timeout.into())
+    /// Claims pending jobs in batches as workers become free, and hands them to the workers.
+    /// Only claims async or sync jobs if `is_async` is given, and both otherwise.
+    /// Returns how many jobs were handed out, once there are no more to claim.
+    ///
+    /// Errs with `FetchError::Timeout` if every worker stays busy for `timeout` while there are
+    /// still jobs to claim.
+    async fn run_pending_tasks(&self, is_async: Option<bool>) -> Result<usize, FetchError> {
+        let slots = &self.slots;
+        let queues = self.queues.as_deref();
+        let mut queued = 0;
+        while !self.in_flight.is_stopping() {
+            let mut free = slots.take_free();
+            if free == 0 {
+                if !db::has_claimable_jobs(&self.pg_pool, is_async, queues).await? {
+                    break;
+                }
+                free = slots
+                    .wait_for_free(self.timeout)
+                    .await
+                    .ok_or(FetchError::Timeout)?;
+            }
+            let bookkeeping = self.bookkeeping();
+            let claimed = match is_async {
+                Some(is_async) => bookkeeping.claim(is_async, queues, free).await,
+                None => self.claim_fairly(&bookkeeping, queues, free).await,
             };
+            let jobs = match claimed {
+                Ok(jobs) => jobs,
+                Err(e) => {
+                    slots.release(free);
+                    return Err(FetchError::FailedLoadingJob(e));
+                }
+            };
+            let claimed = jobs.len();
+            slots.release(free - claimed);
+            queued += claimed;
+            for job in jobs {
+                if job.is_async {
+                    self.run_async_job(job);
+                } else {
+                    self.run_sync_job(job);
+                }
+            }
+            if claimed < free {
+                break;
+            }
+        }
+        Ok(queued)
+    }
+
+    /// Claim up to `free` sync and async jobs, with half of the free workers going to each kind.
+    /// Which kind gets the odd worker alternates, and workers one kind can't use go to the other.
+    /// Once some jobs are claimed, they are leased and have to be run, so a later claim failing
+    /// is logged and the jobs claimed so far are returned.
+    async fn claim_fairly(
+        &self,
+        bookkeeping: &Bookkeeping,
+        queues: Option<&[String]>,
+        free: usize,
+    ) -> Result<Vec<db::BackgroundJob>, sqlx::Error> {
+        let first = self.async_turn.fetch_xor(true, Ordering::Relaxed);
+        let share = free - free / 2;
+        let mut jobs = bookkeeping.claim(first, queues, share).await?;
+        let first_claimed = jobs.len();
+        let mut rest = Vec::new();
+        if jobs.len() < free {
+            rest.push(!first);
+        }
+        if first_claimed == share {
+            rest.push(first);
+        }
+        for is_async in rest {
+            if jobs.len() == free {
+                break;
+            }
+            match bookkeeping.claim(is_async, queues, free - jobs.len()).await {
+                Ok(claimed) => jobs.extend(claimed),
+                Err(e) if jobs.is_empty() => return Err(e),
+                Err(e) => {
+                    log::error!("Failed to fetch jobs: {}", e);
+                    break;
+                }
+            }
         }
+        Ok(jobs)
     }
 
-    fn run_single_async_job(&self, tx: Sender<Event>) {
+    fn run_async_job(&self, job: db::BackgroundJob) {
         let env = Arc::clone(&self.environment);
         let registry = Arc::clone(&self.registry);
+        let middleware = Arc::clone(&self.middleware);
         let pg_pool = self.pg_pool.clone();
-        self.get_single_async_job(tx, |job| {
+        self.spawn_async_job(job, |job, start| {
             async move {
                 let perform_fn = registry.get(&job.job_type).ok_or_else(|| {
                     PerformError::from(format!("Unknown job type {}", job.job_type))
                 })?;
-                perform_fn.perform_async(job.data, env, &pg_pool).await
+                let perform = perform_fn.perform_async(job.data, Arc::clone(&env), &pg_pool);
+                NextAsync::new(&middleware, &start, &env, perform)
+                    .run()
+                    .await
             }
             .boxed()
         });
     }
 
-    fn run_single_sync_job(&self, tx: Sender<Event>) {
+    fn run_sync_job(&self, job: db::BackgroundJob) {
         let env = Arc::clone(&self.environment);
         let registry = Arc::clone(&self.registry);
+        let middleware = AssertUnwindSafe(Arc::clone(&self.middleware));
         let pg_pool = AssertUnwindSafe(self.pg_pool.clone());
 
-        self.get_single_sync_job(tx, move |job| {
+        self.spawn_sync_job(job, move |job, start| {
             let perform_fn = registry
                 .get(&job.job_type)
                 .ok_or_else(|| PerformError::from(format!("Unknown job type {}", job.job_type)))?;
-            perform_fn.perform_sync(job.data, &env, &pg_pool)
+            let perform = || perform_fn.perform_sync(job.data, &env, &pg_pool);
+            NextSync::new(&middleware, &start, &env, perform).run()
         });
     }
 
-    fn get_single_async_job<F>(&self, tx: Sender<Event>, fun: F)
+    /// Run a claimed async job on the executor, releasing its slot once its outcome is recorded
+    fn spawn_async_job<F>(&self, job: db::BackgroundJob, fun: F)
     where
         F: FnOnce(
                 db::BackgroundJob,
+                JobStart,
             ) -> Pin<Box<dyn Future<Output = Result<(), PerformError>> + Send>>
             + Send
             + 'static,
     {
-        let pg_pool = self.pg_pool.clone();
-        let finish_hook = self.on_finish.clone();
-        let _ = self.executor.spawn(async move {
-            let run = || -> Pin<Box<dyn Future<Output = Result<(), PerformError>> + Send>> {
-                async move {
-                    let (transaction, job) =
-                        if let Some((t, j)) = Self::get_next_job(tx, &pg_pool, true).await {
-                            (t, j)
-                        } else {
-                            return Ok(());
-                        };
-                    let job_id = job.id;
-                    // TODO: Need to decide how or if we should handle panics in futures. Wrap with catch_unwind?
-                    // Since we require the `Spawn` trait, the task executor should handle panics, not us?
-                    // However, since we _dont_ handle panics, retry_counter won't be updated
-                    Self::finish_work(fun(job).await, transaction, job_id, finish_hook).await;
-                    Ok(())
-                }
-                .boxed()
-            };
-            match run().await {
-                Ok(_) => {}
-                Err(e) => {
-                    panic!("failed to update job {:?}", e);
-                }
-            };
-        });
+        let attempt = Attempt::start(&job, &self.registry, &self.job_policy);
+        let start = attempt.job_start();
+        let span = AttemptSpan::new(&start, job.trace_context.as_deref());
+        let lease = attempt.lease.clone();
+        let bookkeeping = self.bookkeeping();
+        let slots = self.slots.clone();
+        let spawned = self.executor.spawn(span.instrument(async move {
+            bookkeeping.hooks.started(&start);
+            let result = attempt.run_async(fun(job, start)).await;
+            bookkeeping.finish(result, attempt).await;
+            slots.release(1);
+        }));
+        if let Err(source) = spawned {
+            // the job is left to be claimed again once its lease expires
+            self.bookkeeping().abandon(
+                &lease,
+                FinishError::Spawn {
+                    job_id: lease.job_id,
+                    source,
+                },
+            );
+            self.slots.release(1);
+        }
     }
 
-    fn get_single_sync_job<F>(&self, tx: Sender<Event>, fun: F)
+    /// Run a claimed sync job on the threadpool, releasing its slot once its outcome is recorded
+    fn spawn_sync_job<F>(&self, job: db::BackgroundJob, fun: F)
     where
-        F: FnOnce(db::BackgroundJob) -> Result<(), PerformError> + Send + UnwindSafe + 'static,
+        F: FnOnce(db::BackgroundJob, JobStart) -> Result<(), PerformError>
+            + Send
+            + UnwindSafe
+            + 'static,
     {
-        let pg_pool = self.pg_pool.clone();
-        let finish_hook = self.on_finish.clone();
+        let registry = Arc::clone(&self.registry);
+        let job_policy = self.job_policy;
+        let bookkeeping = self.bookkeeping();
+        let slots = self.slots.clone();
         self.threadpool.spawn_fifo(move || {
-            let res = move || -> Result<(), PerformError> {
-                let (transaction, job) =
-                    if let Some((t, j)) = block_on(Self::get_next_job(tx, &pg_pool, false)) {
-                        (t, j)
-                    } else {
-                        return Ok(());
-                    };
-                let job_id = job.id;
-                let result = catch_unwind(|| fun(job))
-                    .map_err(|e| try_to_extract_panic_info(&e))
-                    .and_then(|r| r);
-                block_on(Self::finish_work(result, transaction, job_id, finish_hook));
-                Ok(())
-            };
-
-            match res() {
-                Ok(_) => {}
-                Err(e) => {
-                    panic!("Failed to update job: {:?}", e);
-                }
-            }
+            let attempt = Attempt::start(&job, &registry, &job_policy);
+            let start = attempt.job_start();
+            let span = AttemptSpan::new(&start, job.trace_context.as_deref());
+            span.in_scope(|| {
+                bookkeeping.hooks.started(&start);
+                let deadline = attempt.deadline();
+                let result = catch_unwind(|| timeout::with_deadline(deadline, || fun(job, start)))
+                    .map_err(|e| Failure::Panic(try_to_extract_panic_info(&*e)))
+                    .and_then(|r| attempt.finished_sync(r));
+                block_on(bookkeeping.finish(result, attempt));
+            });
+            slots.release(1);
         });
     }
 
-    /// returns a transaction/job pair for the next Job
-    async fn get_next_job(tx: Sender<Event>, pg_pool: &PgPool, is_async: bool) -> TxJobPair {
-        let mut transaction = match pg_pool.begin().await {
-            Ok(t) => t,
-            Err(e) => {
-                let _ = tx.send(Event::ErrorLoadingJob(e)).await;
-                return None;
+    fn bookkeeping(&self) -> Bookkeeping {
+        Bookkeeping {
+            pg_pool: self.pg_pool.clone(),
+            worker: Arc::clone(&self.worker_id),
+            keep_history: self.keep_history,
+            lease: self.lease,
+            in_flight: Arc::clone(&self.in_flight),
+            hooks: self.hooks.clone(),
+            on_error: self.on_error.clone(),
+        }
+    }
+}
+
+/// How many times recording the outcome of an attempt is tried before giving up
+const FINISH_ATTEMPTS: usize = 3;
+/// How long to wait before trying to record the outcome of an attempt again
+const FINISH_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Claims jobs for the workers of a runner, records the outcome of attempts at them,
+/// and calls the runner's hooks once they finish
+struct Bookkeeping {
+    pg_pool: PgPool,
+    /// The worker ID jobs are claimed by, and failed attempts are recorded under
+    worker: Arc<str>,
+    keep_history: bool,
+    lease: Duration,
+    in_flight: Arc<InFlight>,
+    hooks: Hooks,
+    on_error: Option<Arc<dyn Fn(FinishError) + Send + Sync + 'static>>,
+}
+
+impl Bookkeeping {
+    /// Claim up to `limit` jobs in one round trip, leasing them to this runner
+    async fn claim(
+        &self,
+        is_async: bool,
+        queues: Option<&[String]>,
+        limit: usize,
+    ) -> Result<Vec<db::BackgroundJob>, sqlx::Error> {
+        let jobs = db::claim_jobs(
+            &self.pg_pool,
+            Some(is_async),
+            queues,
+            &self.worker,
+            self.lease,
+            limit,
+        )
+        .await?;
+        for job in &jobs {
+            self.in_flight.start(Lease::of(job));
+        }
+        Ok(jobs)
+    }
+
+    /// Record the outcome of an attempt, retrying if that fails, and call the hooks.
+    /// Errors are passed to the `on_error` hook rather than returned, since nobody waits on workers.
+    async fn finish(&self, res: Result<(), Failure>, attempt: Attempt) {
+        let job_id = attempt.lease.job_id;
+        let failure = res.err();
+        if let Some(failure) = &failure {
+            log::debug!("Job {} failed to run: {}", job_id, failure.message());
+            if let OnFailure::Kill = attempt.on_failure {
+                log::warn!("Job {} ran out of retries: {}", job_id, failure.message());
             }
-        };
+        }
 
-        let job = match db::find_next_unlocked_job(&mut transaction, Some(is_async)).await {
-            Ok(Some(j)) => {
-                let _ = tx.send(Event::Working).await;
-                j
+        let mut result = self.record(&attempt, failure.as_ref()).await;
+        for _ in 1..FINISH_ATTEMPTS {
+            match &result {
+                Err(FinishError::Sql { source, .. }) => {
+                    log::warn!(
+                        "Failed to record the outcome of job {}, retrying: {}",
+                        job_id,
+                        source
+                    );
+                }
+                _ => break,
             }
-            Ok(None) => {
-                let _ = tx.send(Event::NoJobAvailable).await;
-                return None;
+            timer::Delay::new(FINISH_RETRY_DELAY).await;
+            result = self.record(&attempt, failure.as_ref()).await;
+        }
+
+        let recorded = match result {
+            Ok(()) => {
+                self.in_flight.finish(&attempt.lease);
+                true
             }
             Err(e) => {
-                let _ = tx.send(Event::ErrorLoadingJob(e)).await;
-                return None;
+                self.abandon(&attempt.lease, e);
+                false
             }
         };
-        Some((transaction, job))
-    }
-
-    async fn finish_work(
-        res: Result<(), PerformError>,
-        mut trx: sqlx::Transaction<'static, Postgres>,
-        job_id: i64,
-        on_finish: Option<Arc<dyn Fn(i64) + Send + Sync + 'static>>,
-    ) {
-        match res {
-            Ok(_) => {
-                db::delete_successful_job(&mut trx, job_id)
-                    .await
-                    .map_err(|e| panic!("Failed to delete job: {:?}", e))
-                    .expect("Panic is mapped");
-            }
-            Err(e) => {
-                // TODO: Fix killing the execution
-                // eprintln!("Job {} failed to run: {}", job_id, e);
-                db::update_failed_job(&mut trx, job_id)
-                    .await
-                    .expect(&format!("failed to update failed job: {:?}", e));
-            }
+        let retry_exhausted = matches!(attempt.on_failure, OnFailure::Kill);
+        self.hooks.finished(
+            &attempt.outcome(failure.as_ref(), recorded),
+            retry_exhausted,
+        );
+    }
+
+    /// Stop tracking a claimed job whose outcome won't be recorded, and report why
+    fn abandon(&self, lease: &Lease, e: FinishError) {
+        self.in_flight.finish(lease);
+        log::error!("{}", e);
+        if let Some(f) = &self.on_error {
+            f(e)
         }
+    }
 
-        trx.commit().await.expect("Failed to commit transaction");
-        if let Some(f) = on_finish {
-            f(job_id)
+    /// Record the outcome of an attempt in a single transaction,
+    /// as long as the lease it was started under is still held on the job
+    async fn record(
+        &self,
+        attempt: &Attempt,
+        failure: Option<&Failure>,
+    ) -> Result<(), FinishError> {
+        let job_id = attempt.lease.job_id;
+        let token = attempt.lease.token.as_str();
+        let worker = &*self.worker;
+        let sql = |source| FinishError::Sql { job_id, source };
+        let mut trx = self.pg_pool.begin().await.map_err(sql)?;
+        let leased = match failure {
+            None => db::delete_successful_job(&mut trx, job_id, token)
+                .await
+                .map_err(sql)?,
+            Some(failure) => {
+                let message = failure.message();
+                if self.keep_history {
+                    let (error, panic) = match failure {
+                        Failure::Error(_) | Failure::Timeout(_) => (Some(message.as_str()), None),
+                        Failure::Panic(_) => (None, Some(message.as_str())),
+                    };
+                    let failed_attempt = db::FailedAttempt {
+                        task_id: job_id,
+                        job_type: &attempt.job_type,
+                        attempt: attempt.retries + 1,
+                        error,
+                        panic,
+                        worker,
+                        duration: attempt.started.elapsed(),
+                    };
+                    db::record_failed_attempt(&mut trx, failed_attempt)
+                        .await
+                        .map_err(sql)?;
+                }
+                match attempt.on_failure {
+                    OnFailure::Retry(retry_delay) => {
+                        db::update_failed_job(&mut trx, job_id, token, retry_delay, &message)
+                            .await
+                            .map_err(sql)?
+                    }
+                    OnFailure::Kill => db::kill_job(&mut trx, job_id, token, &message)
+                        .await
+                        .map_err(sql)?,
+                }
+            }
+        };
+        if !leased {
+            // dropping the transaction rolls back the failed attempt recorded in it
+            return Err(FinishError::Lost(job_id));
         }
+        trx.commit().await.map_err(sql)
     }
 }
 
 fn try_to_extract_panic_info(info: &(dyn Any + Send + 'static)) -> PerformError {
-    if let Some(x) = info.downcast_ref::<PanicInfo>() {
-        format!("job panicked: {}", x).into()
-    } else if let Some(x) = info.downcast_ref::<&'static str>() {
+    if let Some(x) = info.downcast_ref::<&'static str>() {
         format!("job panicked: {}", x).into()
     } else if let Some(x) = info.downcast_ref::<String>() {
         format!("job panicked: {}", x).into()
@@ -418,16 +991,14 @@ fn try_to_extract_panic_info(info: &(dyn Any + Send + 'static)) -> PerformError
 impl<Env: Send + Sync + RefUnwindSafe + 'static> Runner<Env> {
     /// Wait for tasks to finish based on timeout
     /// this is mostly used for internal tests
-    async fn wait_for_all_tasks(&self, mut rx: channel::Receiver<Event>, pending: usize) {
+    async fn wait_for_all_tasks(&self, rx: channel::Receiver<Event>, pending: usize) {
         let mut dummy_tasks = pending;
         while dummy_tasks > 0 {
             let timeout = timer::Delay::new(self.timeout);
             futures::select! {
-                msg = rx.next().fuse() => match msg {
-                    // Some(Event::Working) => continue,
-                    Some(Event::NoJobAvailable) => break,
-                    Some(Event::Dummy) => dummy_tasks -= 1,
-                    _ => (),
+                msg = rx.recv().fuse() => match msg {
+                    Ok(Event::Dummy) => dummy_tasks -= 1,
+                    Err(_) => break,
                 },
                 _ = timeout.fuse() => {
                     log::warn!("TASK WAIT TIMED OUT");
@@ -462,17 +1033,21 @@ mod tests {
 
     static TEST_MUTEX: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
-    struct TestGuard<'a>(MutexGuard<'a, ()>);
+    struct TestGuard<'a> {
+        _lock: MutexGuard<'a, ()>,
+    }
     impl<'a> TestGuard<'a> {
         fn lock() -> Self {
-            TestGuard(TEST_MUTEX.lock().unwrap())
+            TestGuard {
+                _lock: TEST_MUTEX.lock().unwrap(),
+            }
         }
     }
 
     impl<'a> Drop for TestGuard<'a> {
         fn drop(&mut self) {
             smol::block_on(async move {
-                sqlx::query("TRUNCATE TABLE _background_tasks")
+                sqlx::query("TRUNCATE TABLE _background_tasks, _background_tasks_dead, _background_tasks_history")
                     .execute(&mut runner().connection().await.unwrap())
                     .await
                     .unwrap()
@@ -536,77 +1111,150 @@ mod tests {
             .0
     }
 
+    fn claim(runner: &Runner<()>, is_async: bool, limit: usize) -> Vec<db::BackgroundJob> {
+        smol::block_on(runner.bookkeeping().claim(is_async, None, limit)).unwrap()
+    }
+
+    fn ids(jobs: &[db::BackgroundJob]) -> Vec<i64> {
+        jobs.iter().map(|j| j.id).collect()
+    }
+
     #[test]
-    fn async_jobs_are_locked_when_fetched() {
+    fn jobs_are_leased_when_claimed() {
         crate::initialize();
         let _guard = TestGuard::lock();
-        let mut runner = runner();
+        let runner = runner();
         let first_job_id = create_dummy_job(&runner, true);
         let second_job_id = create_dummy_job(&runner, true);
-        let fetch_barrier = Arc::new(AssertUnwindSafe(Barrier::new(2)));
-        let fetch_barrier2 = fetch_barrier.clone();
-        let return_barrier = Arc::new(AssertUnwindSafe(Barrier::new(2)));
-        let return_barrier2 = return_barrier.clone();
+        let third_job_id = create_dummy_job(&runner, true);
 
-        let (tx, rx) = channel::bounded(3);
-        let tx0 = tx.clone();
-        runner.on_finish = Some(Arc::new(move |_| {
-            smol::block_on(tx0.send(Event::Dummy)).unwrap();
-        }));
+        assert_eq!(
+            vec![first_job_id, second_job_id],
+            ids(&claim(&runner, true, 2))
+        );
+        assert_eq!(vec![third_job_id], ids(&claim(&runner, true, 2)));
+        assert!(claim(&runner, true, 2).is_empty());
+        assert!(claim(&runner, false, 2).is_empty());
+    }
 
-        smol::run(async move {
-            runner.get_single_async_job(tx.clone(), move |job| {
-                async move {
-                    fetch_barrier.0.wait();
-                    assert_eq!(first_job_id, job.id);
-                    return_barrier.0.wait();
-                    Ok(())
-                }
-                .boxed()
-            });
+    #[test]
+    fn free_workers_are_split_between_sync_and_async_jobs() {
+        crate::initialize();
+        let _guard = TestGuard::lock();
+        let runner = runner();
+        let sync_jobs = [
+            create_dummy_job(&runner, false),
+            create_dummy_job(&runner, false),
+            create_dummy_job(&runner, false),
+        ];
+        let async_job = create_dummy_job(&runner, true);
+        let bookkeeping = runner.bookkeeping();
+        let claim_fairly = |free| {
+            let mut jobs = smol::block_on(runner.claim_fairly(&bookkeeping, None, free)).unwrap();
+            jobs.sort_by_key(|j| j.id);
+            ids(&jobs)
+        };
 
-            fetch_barrier2.0.wait();
-            runner.get_single_async_job(tx.clone(), move |job| {
-                async move {
-                    assert_eq!(second_job_id, job.id);
-                    return_barrier2.0.wait();
-                    Ok(())
-                }
-                .boxed()
-            });
-            runner.wait_for_all_tasks(rx, 2).await;
+        assert_eq!(vec![sync_jobs[0], async_job], claim_fairly(2));
+        // the async jobs ran out, so the sync jobs get every free worker
+        assert_eq!(vec![sync_jobs[1], sync_jobs[2]], claim_fairly(3));
+        assert!(claim_fairly(2).is_empty());
+    }
+
+    #[test]
+    fn jobs_claimed_before_a_claim_fails_are_still_returned() {
+        crate::initialize();
+        let _guard = TestGuard::lock();
+        let runner = runner();
+        let sync_job = create_dummy_job(&runner, false);
+        create_dummy_job(&runner, true);
+        let bookkeeping = runner.bookkeeping();
+        let pool = runner.connection_pool();
+        // Sync jobs are claimed first, then claiming async jobs fails
+        smol::block_on(async {
+            sqlx::query(
+                "CREATE FUNCTION fail_claim() RETURNS trigger AS $$
+                BEGIN RAISE EXCEPTION 'claim failed'; END
+                $$ LANGUAGE plpgsql",
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query(
+                "CREATE TRIGGER fail_claim BEFORE UPDATE ON _background_tasks
+                FOR EACH ROW WHEN (NEW.is_async) EXECUTE FUNCTION fail_claim()",
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
         });
+
+        let claimed = smol::block_on(runner.claim_fairly(&bookkeeping, None, 2));
+        smol::block_on(async {
+            sqlx::query("DROP FUNCTION fail_claim CASCADE")
+                .execute(&pool)
+                .await
+                .unwrap();
+        });
+
+        assert_eq!(vec![sync_job], ids(&claimed.unwrap()));
+        let running: Vec<_> = runner
+            .in_flight
+            .running()
+            .iter()
+            .map(|l| l.job_id)
+            .collect();
+        assert_eq!(vec![sync_job], running);
     }
 
     #[test]
-    fn sync_jobs_are_locked_when_fetched() {
+    fn pending_jobs_are_claimed_in_batches_as_workers_free_up() {
         crate::initialize();
         let _guard = TestGuard::lock();
         let mut runner = runner();
-        let first_job_id = create_dummy_job(&runner, false);
-        let second_job_id = create_dummy_job(&runner, false);
-        let fetch_barrier = Arc::new(AssertUnwindSafe(Barrier::new(2)));
-        let fetch_barrier2 = fetch_barrier.clone();
-        let return_barrier = Arc::new(AssertUnwindSafe(Barrier::new(2)));
-        let return_barrier2 = return_barrier.clone();
+        for _ in 0..5 {
+            create_dummy_job(&runner, false);
+        }
+        let (tx, rx) = channel::bounded(5);
+        runner.hooks.on_finish = Some(Arc::new(move |_: &JobOutcome| {
+            smol::block_on(tx.send(Event::Dummy)).unwrap();
+        }));
+
+        // the runner has 2 workers, and the jobs fail since their type isn't registered
+        assert_eq!(5, smol::block_on(runner.run_all_sync_tasks()).unwrap());
+        smol::block_on(runner.wait_for_all_tasks(rx, 5));
+        let retried = smol::block_on(async {
+            sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM _background_tasks WHERE retries = 1")
+                .fetch_one(&runner.pg_pool)
+                .await
+        })
+        .unwrap()
+        .0;
+        assert_eq!(5, retried);
+    }
+
+    #[test]
+    fn sync_jobs_run_concurrently() {
+        crate::initialize();
+        let _guard = TestGuard::lock();
+        let mut runner = runner();
+        create_dummy_job(&runner, false);
+        create_dummy_job(&runner, false);
+        let barrier = Arc::new(AssertUnwindSafe(Barrier::new(2)));
+        let barrier2 = barrier.clone();
 
         let (tx, rx) = channel::bounded(3);
-        let tx0 = tx.clone();
-        runner.on_finish = Some(Arc::new(move |_| {
-            smol::block_on(tx0.send(Event::Dummy)).unwrap();
+        runner.hooks.on_finish = Some(Arc::new(move |_: &JobOutcome| {
+            smol::block_on(tx.send(Event::Dummy)).unwrap();
         }));
 
-        runner.get_single_sync_job(tx.clone(), move |job| {
-            fetch_barrier.0.wait();
-            assert_eq!(first_job_id, job.id);
-            return_barrier.0.wait();
+        let mut jobs = claim(&runner, false, 2);
+        runner.spawn_sync_job(jobs.remove(0), move |_, _| {
+            barrier.0.wait();
             Ok(())
         });
-
-        fetch_barrier2.0.wait();
-        runner.get_single_sync_job(tx.clone(), move |job| {
-            assert_eq!(second_job_id, job.id);
-            return_barrier2.0.wait();
+        runner.spawn_sync_job(jobs.remove(0), move |_, _| {
+            barrier2.0.wait();
             Ok(())
         });
         smol::block_on(runner.wait_for_all_tasks(rx, 2));
@@ -620,15 +1268,15 @@ mod tests {
         let (tx, rx) = channel::bounded(1);
 
         let mut runner = runner();
-        let tx0 = tx.clone();
-        runner.on_finish = Some(Arc::new(move |_| {
-            smol::block_on(tx0.send(Event::Dummy)).unwrap();
+        runner.hooks.on_finish = Some(Arc::new(move |_: &JobOutcome| {
+            smol::block_on(tx.send(Event::Dummy)).unwrap();
         }));
         create_dummy_job(&runner, true);
 
         smol::run(async move {
             let mut conn = runner.connection().await.unwrap();
-            runner.get_single_async_job(tx.clone(), move |_| async move { Ok(()) }.boxed());
+            let job = claim(&runner, true, 1).remove(0);
+            runner.spawn_async_job(job, move |_, _| async move { Ok(()) }.boxed());
             runner.wait_for_all_tasks(rx, 1).await;
             let remaining_jobs = get_job_count(&mut conn).await;
             assert_eq!(0, remaining_jobs);
@@ -642,11 +1290,11 @@ mod tests {
         let mut runner = runner();
         let job_id = create_dummy_job(&runner, false);
         let (tx, rx) = channel::bounded(3);
-        let tx0 = tx.clone();
-        runner.on_finish = Some(Arc::new(move |_| {
-            smol::block_on(tx0.send(Event::Dummy)).unwrap();
+        runner.hooks.on_finish = Some(Arc::new(move |_: &JobOutcome| {
+            smol::block_on(tx.send(Event::Dummy)).unwrap();
         }));
-        runner.get_single_sync_job(tx.clone(), move |_| panic!());
+        let job = claim(&runner, false, 1).remove(0);
+        runner.spawn_sync_job(job, move |_, _| panic!());
         smol::block_on(runner.wait_for_all_tasks(rx, 1));
 
         let mut conn = smol::block_on(runner.connection()).unwrap();
@@ -0,0 +1,111 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of coil.
+
+// coil is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// coil is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with coil.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Stopping a runner without losing track of the jobs it is running
+
+use crate::lease::Lease;
+use channel::{Receiver, Sender};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often `ShutdownHandle::shutdown` checks whether the running jobs have finished
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The jobs a runner is running, and whether it has been asked to stop.
+/// Shared between the runner, its workers and its shutdown handles.
+pub(crate) struct InFlight {
+    jobs: Mutex<BTreeSet<Lease>>,
+    /// Closed once the runner is asked to stop. Nothing is ever sent on it.
+    stop: Sender<()>,
+    stopped: Receiver<()>,
+}
+
+impl InFlight {
+    pub fn new() -> Self {
+        let (stop, stopped) = channel::bounded(1);
+        Self {
+            jobs: Mutex::new(BTreeSet::new()),
+            stop,
+            stopped,
+        }
+    }
+
+    /// A job was claimed by a worker
+    pub fn start(&self, lease: Lease) {
+        self.jobs.lock().expect("lock poisoned").insert(lease);
+    }
+
+    /// A job was committed, whether it succeeded or failed
+    pub fn finish(&self, lease: &Lease) {
+        self.jobs.lock().expect("lock poisoned").remove(lease);
+    }
+
+    /// The leases on jobs that were claimed and not committed yet
+    pub fn running(&self) -> Vec<Lease> {
+        self.jobs
+            .lock()
+            .expect("lock poisoned")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the runner was asked to stop, after which workers don't claim any new jobs
+    pub fn is_stopping(&self) -> bool {
+        self.stop.is_closed()
+    }
+
+    /// Resolves once the runner is asked to stop
+    pub async fn stopping(&self) {
+        let _ = self.stopped.recv().await;
+    }
+}
+
+/// Stops a runner from another task or thread.
+/// Get one with `Runner::shutdown_handle`.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    in_flight: Arc<InFlight>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(in_flight: Arc<InFlight>) -> Self {
+        Self { in_flight }
+    }
+
+    /// Stop claiming new jobs, and wait up to `deadline` for the jobs that are running to finish.
+    /// `Runner::run_forever` returns once this is called.
+    ///
+    /// Returns the IDs of the jobs which were still running once the deadline passed.
+    /// If the process exits, they are run again by another runner once their lease has expired.
+    pub async fn shutdown(&self, deadline: Duration) -> Vec<i64> {
+        self.in_flight.stop.close();
+        let started = Instant::now();
+        loop {
+            let running = self.in_flight.running();
+            if running.is_empty() || started.elapsed() >= deadline {
+                return running.into_iter().map(|lease| lease.job_id).collect();
+            }
+            timer::Delay::new(DRAIN_INTERVAL).await;
+        }
+    }
+
+    /// Whether `shutdown` was called
+    pub fn is_shutdown(&self) -> bool {
+        self.in_flight.is_stopping()
+    }
+}
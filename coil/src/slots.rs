@@ -0,0 +1,65 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of coil.
+
+// coil is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// coil is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with coil.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Keeping track of how many more jobs a runner can take on
+
+use channel::{Receiver, Sender};
+use futures::FutureExt;
+use std::time::Duration;
+
+/// The workers of a runner which jobs can be dispatched to.
+/// A slot is taken when a job is claimed for it, and released once its outcome is recorded.
+#[derive(Clone)]
+pub(crate) struct Slots {
+    release: Sender<()>,
+    free: Receiver<()>,
+}
+
+impl Slots {
+    pub fn new(slots: usize) -> Self {
+        let (release, free) = channel::bounded(slots.max(1));
+        for _ in 0..slots {
+            let _ = release.try_send(());
+        }
+        Self { release, free }
+    }
+
+    /// Take every slot that is free right now
+    pub fn take_free(&self) -> usize {
+        let mut taken = 0;
+        while self.free.try_recv().is_ok() {
+            taken += 1;
+        }
+        taken
+    }
+
+    /// Wait up to `timeout` for a slot to be free, then take every slot that is.
+    /// Returns how many slots were taken, or `None` if none became free in time.
+    pub async fn wait_for_free(&self, timeout: Duration) -> Option<usize> {
+        futures::select! {
+            slot = self.free.recv().fuse() => slot.ok()?,
+            _ = timer::Delay::new(timeout).fuse() => return None,
+        }
+        Some(1 + self.take_free())
+    }
+
+    /// Release slots which were taken
+    pub fn release(&self, slots: usize) {
+        for _ in 0..slots {
+            let _ = self.release.try_send(());
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of coil.
+
+// coil is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// coil is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with coil.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cooperative cancellation of synchronous jobs which run past their timeout
+
+use std::cell::Cell;
+use std::time::Instant;
+
+thread_local! {
+    /// When the sync job running on this thread times out
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Whether the synchronous job running on this thread has run past its timeout.
+///
+/// Sync jobs can't be interrupted, so long running ones should check this
+/// regularly and return early once it is `true`.
+/// A sync job which returns after its deadline is recorded as timed out, whatever it returned.
+/// Always `false` outside of sync jobs.
+///
+/// # Example
+/// ```ignore
+/// #[coil::background_job(timeout = "5m")]
+/// fn resize_images(ids: Vec<u32>) -> Result<(), PerformError> {
+///     for id in ids {
+///         if coil::is_cancelled() {
+///             return Err("cancelled".into());
+///         }
+///         resize(id)?;
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn is_cancelled() -> bool {
+    DEADLINE.with(|deadline| {
+        deadline
+            .get()
+            .map(|deadline| Instant::now() >= deadline)
+            .unwrap_or(false)
+    })
+}
+
+/// Run `f` with `is_cancelled` becoming `true` once `deadline` has passed
+pub(crate) fn with_deadline<T>(deadline: Option<Instant>, f: impl FnOnce() -> T) -> T {
+    struct Reset(Option<Instant>);
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            DEADLINE.with(|deadline| deadline.set(self.0));
+        }
+    }
+
+    let _reset = Reset(DEADLINE.with(|d| d.replace(deadline)));
+    f()
+}
@@ -0,0 +1,136 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of coil.
+
+// coil is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// coil is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with coil.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Spans for job attempts, continuing the trace a job was enqueued from.
+//! Without the `tracing` feature these are no-ops.
+
+use crate::hooks::JobStart;
+use futures::Future;
+
+#[cfg(feature = "tracing")]
+use std::sync::OnceLock;
+
+/// Carries the trace a job was enqueued from over to the runner performing it,
+/// for tracers like OpenTelemetry whose context can be serialized.
+///
+/// Set one with `set_propagator`, both where jobs are enqueued and where they are run.
+///
+/// # Example
+/// ```ignore
+/// struct OtelPropagator(TraceContextPropagator);
+///
+/// impl coil::Propagator for OtelPropagator {
+///     fn inject(&self, span: &tracing::Span) -> Option<String> {
+///         let mut carrier = HashMap::new();
+///         self.0.inject_context(&span.context(), &mut carrier);
+///         serde_json::to_string(&carrier).ok()
+///     }
+///
+///     fn extract(&self, context: &str, span: &tracing::Span) {
+///         if let Ok(carrier) = serde_json::from_str::<HashMap<String, String>>(context) {
+///             span.set_parent(self.0.extract(&carrier));
+///         }
+///     }
+/// }
+/// ```
+#[cfg(feature = "tracing")]
+pub trait Propagator: Send + Sync + 'static {
+    /// Serialize the context of `span`, the current span when a job is enqueued
+    fn inject(&self, span: &tracing::Span) -> Option<String>;
+
+    /// Make `span`, the span of an attempt at running a job, continue the context `inject` serialized
+    fn extract(&self, context: &str, span: &tracing::Span);
+}
+
+#[cfg(feature = "tracing")]
+static PROPAGATOR: OnceLock<Box<dyn Propagator>> = OnceLock::new();
+
+/// Set the propagator jobs carry their trace over with.
+/// Returns `false` if one was set already, in which case it is kept.
+#[cfg(feature = "tracing")]
+pub fn set_propagator(propagator: impl Propagator) -> bool {
+    PROPAGATOR.set(Box::new(propagator)).is_ok()
+}
+
+/// The context of the current span, to be stored along with a job being enqueued
+pub(crate) fn current_context() -> Option<String> {
+    #[cfg(feature = "tracing")]
+    {
+        PROPAGATOR
+            .get()
+            .and_then(|propagator| propagator.inject(&tracing::Span::current()))
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        None
+    }
+}
+
+/// The span an attempt at running a job runs in
+pub(crate) struct AttemptSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl AttemptSpan {
+    /// The span for the attempt `start`, continuing the trace in `context`
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn new(start: &JobStart, context: Option<&str>) -> Self {
+        #[cfg(feature = "tracing")]
+        {
+            let span = tracing::info_span!(
+                parent: None,
+                "coil.job",
+                job.id = start.id,
+                job.r#type = start.job_type.as_str(),
+                job.attempt = start.attempt,
+                job.queue = start.queue.as_str(),
+            );
+            if let (Some(propagator), Some(context)) = (PROPAGATOR.get(), context) {
+                propagator.extract(context, &span);
+            }
+            Self { span }
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            Self {}
+        }
+    }
+
+    /// Run `f` in the span
+    pub fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "tracing")]
+        {
+            self.span.in_scope(f)
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            f()
+        }
+    }
+
+    /// Poll `future` in the span
+    pub fn instrument<F: Future>(self, future: F) -> impl Future<Output = F::Output> {
+        #[cfg(feature = "tracing")]
+        {
+            tracing::Instrument::instrument(future, self.span)
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            future
+        }
+    }
+}
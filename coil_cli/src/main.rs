@@ -0,0 +1,400 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of coil.
+
+// coil is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// coil is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with coil.  If not, see <http://www.gnu.org/licenses/>.
+
+//! `coil`: inspect and administer the job queue of a Postgres database
+
+use anyhow::{bail, Context, Result};
+use coil::admin::{self, JobFilter, JobState, QueuedJob};
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, SystemTime};
+use structopt::StructOpt;
+
+/// Inspect and administer the coil job queue of the database at `DATABASE_URL`
+#[derive(StructOpt)]
+#[structopt(name = "coil")]
+enum Command {
+    /// Run the coil migrations
+    Migrate,
+    /// Count the jobs of each type in each state
+    Stats,
+    /// List the jobs in the queue, oldest first
+    List {
+        #[structopt(flatten)]
+        filter: Filter,
+        /// List at most this many jobs
+        #[structopt(long, default_value = "50")]
+        limit: u32,
+    },
+    /// Show a job, with its data decoded to JSON
+    Show { id: i64 },
+    /// Make a job due to run now, moving it out of the dead letter table if it ran out of retries
+    Retry {
+        /// The job to retry
+        #[structopt(
+            required_unless_one = FILTER_ARGS,
+            conflicts_with_all = FILTER_ARGS
+        )]
+        id: Option<i64>,
+        /// Retry every failed and dead job matching these filters instead
+        #[structopt(flatten)]
+        filter: Filter,
+    },
+    /// Delete a job from the queue
+    Delete {
+        /// The job to delete
+        #[structopt(
+            required_unless_one = FILTER_ARGS,
+            conflicts_with_all = FILTER_ARGS
+        )]
+        id: Option<i64>,
+        /// Delete the jobs matching these filters instead
+        #[structopt(flatten)]
+        filter: Filter,
+    },
+    /// Delete every job which ran out of retries
+    PurgeFailed,
+    /// Follow jobs as they are enqueued, change state and leave the queue
+    Tail {
+        /// How often to check the queue
+        #[structopt(long, default_value = "1s", parse(try_from_str = humantime::parse_duration))]
+        interval: Duration,
+    },
+}
+
+/// The arguments of `Filter`, which commands taking either a job ID or filters require one of
+const FILTER_ARGS: &[&str] = &["job-type", "queue", "failed", "older-than"];
+
+#[derive(StructOpt)]
+struct Filter {
+    /// Only jobs of this type
+    #[structopt(long = "type")]
+    job_type: Option<String>,
+    /// Only jobs enqueued on this queue
+    #[structopt(long)]
+    queue: Option<String>,
+    /// Only jobs which failed at least once
+    #[structopt(long)]
+    failed: bool,
+    /// Only jobs enqueued longer ago than this, like `2h`
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    older_than: Option<Duration>,
+}
+
+impl Filter {
+    fn to_job_filter(&self) -> JobFilter {
+        let mut filter = JobFilter::new();
+        if let Some(job_type) = &self.job_type {
+            filter = filter.job_type(job_type.as_str());
+        }
+        if let Some(queue) = &self.queue {
+            filter = filter.queue(queue.as_str());
+        }
+        if self.failed {
+            filter = filter.failed_only();
+        }
+        if let Some(older_than) = self.older_than {
+            filter = filter.created_before(SystemTime::now() - older_than);
+        }
+        filter
+    }
+}
+
+fn main() -> Result<()> {
+    let command = Command::from_args();
+    let url = dotenv::var("DATABASE_URL").context("DATABASE_URL must be set")?;
+    smol::run(async {
+        let pool = PgPool::connect(&url)
+            .await
+            .with_context(|| format!("Couldn't connect to {}", url))?;
+        run(command, &pool).await
+    })
+}
+
+async fn run(command: Command, pool: &PgPool) -> Result<()> {
+    match command {
+        Command::Migrate => {
+            coil::migrate(pool).await?;
+            println!("Migrations are up to date");
+        }
+        Command::Stats => stats(pool).await?,
+        Command::List { filter, limit } => {
+            let jobs = admin::list_jobs(pool, &filter.to_job_filter().limit(limit)).await?;
+            print_jobs(&jobs);
+        }
+        Command::Show { id } => show(pool, id).await?,
+        Command::Retry { id, filter } => {
+            let retried = match id {
+                Some(id) => admin::retry_job(pool, id).await? as u64,
+                None => admin::retry_jobs(pool, &filter.to_job_filter().failed_only()).await?,
+            };
+            println!("Retried {} jobs", retried);
+        }
+        Command::Delete { id, filter } => {
+            let deleted = match id {
+                Some(id) => admin::delete_job(pool, id).await? as u64,
+                None => admin::delete_jobs(pool, &filter.to_job_filter()).await?,
+            };
+            println!("Deleted {} jobs", deleted);
+        }
+        Command::PurgeFailed => {
+            let purged = admin::purge_dead_jobs(pool).await?;
+            println!("Purged {} dead jobs", purged);
+        }
+        Command::Tail { interval } => tail(pool, interval).await?,
+    }
+    Ok(())
+}
+
+async fn stats(pool: &PgPool) -> Result<()> {
+    const STATES: [JobState; 5] = [
+        JobState::Pending,
+        JobState::Scheduled,
+        JobState::Running,
+        JobState::Failed,
+        JobState::Dead,
+    ];
+    let mut by_type = BTreeMap::<String, HashMap<JobState, i64>>::new();
+    for count in admin::count_jobs(pool).await? {
+        by_type
+            .entry(count.job_type)
+            .or_default()
+            .insert(count.state, count.count);
+    }
+    print!("{:<40}", "TYPE");
+    for state in &STATES {
+        print!(" {:>10}", state.to_string().to_uppercase());
+    }
+    println!();
+    for (job_type, counts) in &by_type {
+        print!("{:<40}", job_type);
+        for state in &STATES {
+            print!(" {:>10}", counts.get(state).copied().unwrap_or(0));
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn print_jobs(jobs: &[QueuedJob]) {
+    println!(
+        "{:>10} {:<32} {:<16} {:<10} {:>7} {:<20} LAST ERROR",
+        "ID", "TYPE", "QUEUE", "STATE", "RETRIES", "RUN AT"
+    );
+    for job in jobs {
+        println!(
+            "{:>10} {:<32} {:<16} {:<10} {:>7} {:<20} {}",
+            job.id,
+            job.job_type,
+            job.queue,
+            job.state,
+            job.retries,
+            humantime::format_rfc3339_seconds(job.run_at).to_string(),
+            job.last_error.as_deref().unwrap_or("")
+        );
+    }
+}
+
+async fn show(pool: &PgPool, id: i64) -> Result<()> {
+    if let Some(job) = admin::get_job(pool, id).await? {
+        println!("id:           {}", job.id);
+        println!("type:         {}", job.job_type);
+        println!("queue:        {}", job.queue);
+        println!("state:        {}", job.state);
+        println!("async:        {}", job.is_async);
+        println!("priority:     {}", job.priority);
+        println!("retries:      {}", job.retries);
+        println!(
+            "created at:   {}",
+            humantime::format_rfc3339_seconds(job.created_at)
+        );
+        println!(
+            "run at:       {}",
+            humantime::format_rfc3339_seconds(job.run_at)
+        );
+        if let (Some(worker), Some(until)) = (&job.locked_by, job.locked_until) {
+            println!(
+                "leased to:    {} until {}",
+                worker,
+                humantime::format_rfc3339_seconds(until)
+            );
+        }
+        if let Some(error) = &job.last_error {
+            println!("last error:   {}", error);
+        }
+        println!("data:         {}", decode_data(&job.data));
+        return Ok(());
+    }
+    match admin::get_dead_job(pool, id).await? {
+        Some(job) => {
+            println!("id:           {}", job.id);
+            println!("type:         {}", job.job_type);
+            println!("queue:        {}", job.queue);
+            println!("state:        {}", JobState::Dead);
+            println!("async:        {}", job.is_async);
+            println!("priority:     {}", job.priority);
+            println!("retries:      {}", job.retries);
+            println!(
+                "created at:   {}",
+                humantime::format_rfc3339_seconds(job.created_at)
+            );
+            println!(
+                "died at:      {}",
+                humantime::format_rfc3339_seconds(job.died_at)
+            );
+            println!("error:        {}", job.error);
+            println!("data:         {}", decode_data(&job.data));
+            Ok(())
+        }
+        None => bail!("There is no job with ID {}", id),
+    }
+}
+
+/// The MessagePack encoded data of a job, as pretty printed JSON
+fn decode_data(data: &[u8]) -> String {
+    match rmp_serde::from_read::<_, serde_json::Value>(data) {
+        Ok(value) => serde_json::to_string_pretty(&value).unwrap_or_else(|e| e.to_string()),
+        Err(e) => format!("couldn't decode the data: {}", e),
+    }
+}
+
+async fn tail(pool: &PgPool, interval: Duration) -> Result<()> {
+    let mut seen = HashMap::<i64, JobState>::new();
+    let mut first = true;
+    loop {
+        let jobs = admin::list_job_summaries(pool, &JobFilter::new()).await?;
+        let mut current = HashMap::with_capacity(jobs.len());
+        for job in &jobs {
+            match seen.get(&job.id) {
+                None if !first => println!(
+                    "{} {} enqueued on {}: {}",
+                    job.id, job.job_type, job.queue, job.state
+                ),
+                Some(&state) if state != job.state => match &job.last_error {
+                    Some(error) if job.state == JobState::Failed => {
+                        println!("{} {} failed: {}", job.id, job.job_type, error)
+                    }
+                    _ => println!("{} {} {}", job.id, job.job_type, job.state),
+                },
+                _ => (),
+            }
+            current.insert(job.id, job.state);
+        }
+        let gone: Vec<i64> = seen
+            .keys()
+            .filter(|id| !current.contains_key(id))
+            .copied()
+            .collect();
+        if !gone.is_empty() {
+            let dead: HashMap<i64, String> = admin::dead_job_summaries(pool, &gone)
+                .await?
+                .into_iter()
+                .map(|job| (job.id, job.last_error.unwrap_or_default()))
+                .collect();
+            for id in gone {
+                match dead.get(&id) {
+                    Some(error) => println!("{} dead: {}", id, error),
+                    None => println!("{} done", id),
+                }
+            }
+        }
+        seen = current;
+        first = false;
+        smol::Timer::new(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, structopt::clap::Error> {
+        Command::from_iter_safe(std::iter::once("coil").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn job_data_is_decoded_to_json() {
+        let data = rmp_serde::to_vec(&("tohru", 32)).unwrap();
+        assert_eq!("[\n  \"tohru\",\n  32\n]", decode_data(&data));
+        assert!(decode_data(&[0xc1]).starts_with("couldn't decode the data"));
+    }
+
+    #[test]
+    fn retry_takes_an_id_or_filters() {
+        assert!(matches!(
+            parse(&["retry", "42"]),
+            Ok(Command::Retry { id: Some(42), .. })
+        ));
+        match parse(&["retry", "--type", "resize_image"]) {
+            Ok(Command::Retry { id: None, filter }) => {
+                assert_eq!(Some("resize_image"), filter.job_type.as_deref())
+            }
+            _ => panic!("expected a retry by type"),
+        }
+        assert!(matches!(
+            parse(&["retry", "--queue", "thumbnails"]),
+            Ok(Command::Retry { id: None, .. })
+        ));
+        assert!(parse(&["retry"]).is_err());
+        assert!(parse(&["retry", "42", "--type", "resize_image"]).is_err());
+        assert!(parse(&["retry", "42", "--older-than", "2d"]).is_err());
+    }
+
+    #[test]
+    fn delete_takes_an_id_or_filters() {
+        assert!(matches!(
+            parse(&["delete", "42"]),
+            Ok(Command::Delete { id: Some(42), .. })
+        ));
+        match parse(&["delete", "--queue", "thumbnails", "--older-than", "2d"]) {
+            Ok(Command::Delete { id: None, filter }) => {
+                assert_eq!(Some("thumbnails"), filter.queue.as_deref());
+                assert_eq!(
+                    Some(Duration::from_secs(2 * 24 * 60 * 60)),
+                    filter.older_than
+                );
+            }
+            _ => panic!("expected a delete by filter"),
+        }
+        match parse(&["delete", "--older-than", "2d"]) {
+            Ok(Command::Delete { id: None, filter }) => assert!(filter.older_than.is_some()),
+            _ => panic!("expected a delete by age"),
+        }
+        match parse(&["delete", "--failed"]) {
+            Ok(Command::Delete { id: None, filter }) => assert!(filter.failed),
+            _ => panic!("expected a delete of failed jobs"),
+        }
+        assert!(parse(&["delete"]).is_err());
+        assert!(parse(&["delete", "42", "--queue", "thumbnails"]).is_err());
+        assert!(parse(&["delete", "42", "--failed"]).is_err());
+    }
+
+    #[test]
+    fn list_and_tail_have_defaults() {
+        match parse(&["list", "--failed"]) {
+            Ok(Command::List { filter, limit }) => {
+                assert!(filter.failed);
+                assert_eq!(50, limit);
+            }
+            _ => panic!("expected a list"),
+        }
+        assert!(matches!(
+            parse(&["tail"]),
+            Ok(Command::Tail { interval }) if interval == Duration::from_secs(1)
+        ));
+        assert!(parse(&["tail", "--interval", "soon"]).is_err());
+    }
+}
@@ -1,12 +1,22 @@
 use crate::diagnostic_shim::*;
+use crate::options::JobOptions;
 use proc_macro2::TokenStream;
 use quote::quote;
 use std::borrow::Cow;
 use syn::punctuated::Punctuated;
 use syn::spanned::Spanned;
 
-pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
+pub fn expand(item: syn::ItemFn, options: JobOptions) -> Result<TokenStream, Diagnostic> {
     let job = BackgroundJob::try_from(item)?;
+    let job_items = options.job_items();
+    let job_type = options.job_type(&job.name.to_string());
+    let registrations = options.registrations(&syn::Ident::new("Job", job.name.span()));
+    if let (false, Some(register)) = (job.generics_exist, options.register()) {
+        return Err(register
+            .span()
+            .error("`register(...)` only applies to generic jobs")
+            .help("jobs without type parameters are registered automatically"));
+    }
 
     let attrs = job.attrs;
     let vis = job.visibility;
@@ -26,6 +36,9 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
     let body = connection_arg.wrap(job.body);
     let (impl_generics, ty_generics, where_clause) = job.generics.split_for_impl();
     let is_async = job.is_async;
+    let mut job_generics = job.generics.clone();
+    let type_parameters = type_parameters(quote!(#name :: Job #ty_generics), &mut job_generics);
+    let job_where_clause = &job_generics.where_clause;
 
     // FIXME: this proc-macro needs some love ...
     // I should probably split the `Job Trait` into `Async Job` and `Sync Job`
@@ -42,10 +55,12 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
             }
 
             #[coil::async_trait::async_trait]
-            impl #impl_generics coil::Job for #name :: Job #ty_generics #where_clause {
+            impl #impl_generics coil::Job for #name :: Job #ty_generics #job_where_clause {
                 type Environment = #env_type;
-                const JOB_TYPE: &'static str = stringify!(#name);
+                const JOB_TYPE: &'static str = #job_type;
                 const ASYNC: bool = #is_async;
+                #job_items
+                #type_parameters
 
                 async #fn_token perform_async(self,
                     #env_pat: std::sync::Arc<Self::Environment>,
@@ -57,7 +72,7 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
                 }
             }
 
-            pub(crate) mod #name {
+            #vis mod #name {
                 use super::*;
 
                 #[derive(coil::Serialize, coil::Deserialize)]
@@ -65,6 +80,8 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
                 pub struct Job #ty_generics {
                     #(#struct_def),*
                 }
+
+                #registrations
             }
         }
     } else if job.generics_exist && !job.is_async {
@@ -77,10 +94,12 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
             }
 
             #[coil::async_trait::async_trait]
-            impl #impl_generics coil::Job for #name :: Job #ty_generics #where_clause {
+            impl #impl_generics coil::Job for #name :: Job #ty_generics #job_where_clause {
                 type Environment = #env_type;
-                const JOB_TYPE: &'static str = stringify!(#name);
+                const JOB_TYPE: &'static str = #job_type;
                 const ASYNC: bool = #is_async;
+                #job_items
+                #type_parameters
 
                 #fn_token perform(self, #env_pat: &Self::Environment, #pool_pat: &#pool_ty) #return_type {
                     let Self { #(#arg_names_1),* } = self;
@@ -88,7 +107,7 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
                 }
             }
 
-            pub(crate) mod #name {
+            #vis mod #name {
                 use super::*;
 
                 #[derive(coil::Serialize, coil::Deserialize)]
@@ -96,6 +115,8 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
                 pub struct Job #ty_generics {
                     #(#struct_def),*
                 }
+
+                #registrations
             }
         }
     } else if !job.generics_exist && job.is_async {
@@ -110,8 +131,9 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
             #[coil::async_trait::async_trait]
             impl coil::Job for #name :: Job {
                 type Environment = #env_type;
-                const JOB_TYPE: &'static str = stringify!(#name);
+                const JOB_TYPE: &'static str = #job_type;
                 const ASYNC: bool = #is_async;
+                #job_items
 
                 async #fn_token perform_async(self,
                     #env_pat: std::sync::Arc<Self::Environment>,
@@ -123,7 +145,7 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
                 }
             }
 
-            pub(crate) mod #name {
+            #vis mod #name {
                 use super::*;
 
                 #[derive(coil::Serialize, coil::Deserialize)]
@@ -147,8 +169,9 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
             #[coil::async_trait::async_trait]
             impl coil::Job for #name :: Job {
                 type Environment = #env_type;
-                const JOB_TYPE: &'static str = stringify!(#name);
+                const JOB_TYPE: &'static str = #job_type;
                 const ASYNC: bool = #is_async;
+                #job_items
 
                 #fn_token perform(self, #env_pat: &Self::Environment, #pool_pat: &#pool_ty) #return_type {
                     let Self { #(#arg_names_1),* } = self;
@@ -156,7 +179,7 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
                 }
             }
 
-            pub(crate) mod #name {
+            #vis mod #name {
                 use super::*;
 
                 #[derive(coil::Serialize, coil::Deserialize)]
@@ -173,6 +196,25 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
     Ok(res)
 }
 
+/// Implements `Job::type_parameters` for a generic `job`, so that each instantiation is stored
+/// as its own job type, and bounds the impl in `generics` to the instantiations given a name
+pub fn type_parameters(job: TokenStream, generics: &mut syn::Generics) -> Option<TokenStream> {
+    generics.type_params().next()?;
+    generics
+        .make_where_clause()
+        .predicates
+        .push(syn::parse_quote!(#job: coil::JobInstantiation));
+    Some(quote! {
+        fn type_parameters() -> Option<String> {
+            Some(format!("<{}>", <#job as coil::JobInstantiation>::NAME))
+        }
+
+        fn runs_legacy_job_type() -> bool {
+            <#job as coil::JobInstantiation>::LEGACY
+        }
+    })
+}
+
 struct BackgroundJob {
     is_async: bool,
     attrs: Vec<syn::Attribute>,
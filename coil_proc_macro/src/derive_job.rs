@@ -0,0 +1,98 @@
+use crate::diagnostic_shim::*;
+use crate::options::DeriveOptions;
+use proc_macro2::TokenStream;
+use quote::quote;
+
+pub fn expand(item: syn::DeriveInput, options: DeriveOptions) -> Result<TokenStream, Diagnostic> {
+    let name = &item.ident;
+    let generics_exist = item.generics.type_params().next().is_some();
+    if let (false, Some(register)) = (generics_exist, options.job.register()) {
+        return Err(register
+            .span()
+            .error("`register(...)` only applies to generic jobs")
+            .help("jobs without type parameters are registered automatically"));
+    }
+
+    // Like serde's derives, bound each type parameter by what storing the job needs
+    let mut generics = item.generics.clone();
+    let params: Vec<_> = generics.type_params().map(|p| p.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    for param in params {
+        where_clause
+            .predicates
+            .push(syn::parse_quote!(#param: coil::Serialize + coil::DeserializeOwned));
+    }
+    let (_, item_ty_generics, _) = item.generics.split_for_impl();
+    let type_parameters =
+        crate::background_job::type_parameters(quote!(#name #item_ty_generics), &mut generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let env_type = options
+        .env
+        .as_ref()
+        .map(|env| quote!(#env))
+        .unwrap_or_else(|| quote!(()));
+    let job_type = options.job.job_type(&to_snake_case(&name.to_string()));
+    let job_items = options.job.job_items();
+    let is_async = options.is_async;
+    let perform = if is_async {
+        quote! {
+            async fn perform_async(
+                self,
+                env: std::sync::Arc<Self::Environment>,
+                pool: &coil::sqlx::PgPool,
+            ) -> Result<(), coil::PerformError> {
+                <Self as coil::PerformAsync>::perform(self, env, pool).await
+            }
+        }
+    } else {
+        quote! {
+            fn perform(
+                self,
+                env: &Self::Environment,
+                pool: &coil::sqlx::PgPool,
+            ) -> Result<(), coil::PerformError> {
+                <Self as coil::Perform>::perform(self, env, pool)
+            }
+        }
+    };
+    let registrations = if generics_exist {
+        options.job.registrations(name)
+    } else {
+        quote!(coil::register_job!(#name);)
+    };
+
+    Ok(quote! {
+        #[coil::async_trait::async_trait]
+        impl #impl_generics coil::Job for #name #ty_generics #where_clause {
+            type Environment = #env_type;
+            const JOB_TYPE: &'static str = #job_type;
+            const ASYNC: bool = #is_async;
+            #job_items
+            #type_parameters
+
+            #perform
+        }
+
+        #registrations
+    })
+}
+
+/// `SendInvoice` to `send_invoice`, and `HTTPRequest` to `http_request`
+fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake_case = String::with_capacity(name.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next = chars.get(i + 1).copied();
+            let starts_word = prev.is_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_uppercase() && next.is_some_and(char::is_lowercase));
+            if starts_word {
+                snake_case.push('_');
+            }
+        }
+        snake_case.extend(c.to_lowercase());
+    }
+    snake_case
+}
@@ -5,16 +5,21 @@
 extern crate proc_macro;
 
 mod background_job;
+mod derive_job;
 mod diagnostic_shim;
+mod options;
 
 use proc_macro::TokenStream;
-use proc_macro2::Span;
-use syn::{parse_macro_input, ItemFn};
+use syn::{parse_macro_input, DeriveInput, ItemFn};
 
 use diagnostic_shim::*;
 
 /// The attribute macro for creating background jobs.
 ///
+/// The function is replaced by one returning the job, of the type `<function name>::Job`.
+/// The module and the type are as visible as the function, so jobs from `pub` functions can be
+/// named, enqueued and registered from other crates.
+///
 /// # Examples
 ///
 /// ```ignore
@@ -33,19 +38,106 @@ use diagnostic_shim::*;
 ///     content.modify().send_to_actor_pipeline();
 /// }
 /// ````
+///
+/// # Options
+///
+/// - `name = "<name>"`: the job type the job is stored with. Defaults to the name of the
+///   function. Jobs already in the queue are only run if their type still matches, so set this
+///   before renaming or moving a job function.
+/// - `aliases = ["<name>", ...]`: other job types the job is run for, like the names it had
+///   before it was renamed. Jobs are always enqueued with `name`.
+/// - `priority = <i32>`: the priority the job is enqueued with by default. Jobs with a higher
+///   priority are run first. Defaults to `0`.
+/// - `queue = "<name>"`: the queue the job is enqueued on. Runners only run jobs from the queues
+///   they were built to serve. Defaults to `"default"`.
+/// - `max_retries = <u32>`: how many times the job is retried before it is moved to the dead
+///   letter table. Defaults to the maximum the runner was built with.
+/// - `timeout = "<duration>"`: how long an attempt may run before it is failed as timed out,
+///   like `"500ms"`, `"30s"`, `"5m"` or `"1h"`. Defaults to the timeout the runner was built with.
+/// - `unique` or `unique = <bool>`: don't enqueue the job while a job of the same type with the
+///   same arguments is still in the queue. Defaults to `false`.
+/// - `register(<type> = "<name>", ...)`: name these instantiations of a generic job, and
+///   register them with `coil::register_job!` so runners pick them up without
+///   `Builder::register_job`. Each entry is the type of the job's only type parameter, like
+///   `String`, or all of its generic arguments in angle brackets, like `<String, u32>`, followed
+///   by the name the instantiation is stored under, like `String = "string"`. Only named
+///   instantiations implement `coil::Job`, and their names have to stay the same for jobs already
+///   in the queue to be run. Jobs enqueued before generic jobs were stored per instantiation
+///   are run by the first instantiation listed, see `Job::type_parameters`.
+///
+/// ```ignore
+/// #[background_job(priority = 10, queue = "mailers")]
+/// async fn send_password_reset(email: String) -> Result<(), PerformError> {
+///     mailer::send_reset(email).await
+/// }
+/// ```
+///
+/// ```ignore
+/// #[background_job(
+///     name = "billing.charge_card",
+///     aliases = ["charge"],
+///     unique,
+///     register(Card = "card", BankAccount = "bank_account")
+/// )]
+/// fn charge<M: PaymentMethod>(method: M, cents: u64) -> Result<(), PerformError> {
+///     method.charge(cents)
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn background_job(attr: TokenStream, item: TokenStream) -> TokenStream {
-    if !attr.is_empty() {
-        return syn::Error::new(
-            Span::call_site(),
-            "coil::background_job does not take arguments",
-        )
-        .to_compile_error()
-        .into();
-    }
-
+    let options = parse_macro_input!(attr as options::JobOptions);
     let item = parse_macro_input!(item as ItemFn);
-    emit_errors(background_job::expand(item))
+    emit_errors(background_job::expand(item, options))
+}
+
+/// Derive `coil::Job` for a type of your own, with the work it does implemented by hand with
+/// `coil::Perform`, or with `coil::PerformAsync` for asynchronous jobs.
+/// The type must also implement `Serialize` and `Deserialize`, which is how it is stored.
+///
+/// # Options
+///
+/// Options are given with `#[coil(...)]`, and take the same options as `#[background_job]`.
+/// The job type defaults to the name of the type in `snake_case`. In addition:
+///
+/// - `env = <type>`: the environment the job is run with. Defaults to `()`.
+/// - `async`: the job is asynchronous, and implements `coil::PerformAsync` instead of
+///   `coil::Perform`.
+///
+/// Types without type parameters are registered automatically. Generic types name their
+/// instantiations with `register(...)`, which also registers them. Their type parameters are
+/// bound by `Serialize` and `DeserializeOwned`, and any other bound their `Perform` impl relies on
+/// goes in the definition of the type. Their `Perform` impl is bounded by
+/// `Self: coil::JobInstantiation`, like the generated `coil::Job` impl.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize, coil::Job)]
+/// #[coil(env = Environment, async, name = "billing.send_invoice", queue = "mailers")]
+/// struct SendInvoice {
+///     customer: i64,
+/// }
+///
+/// impl SendInvoice {
+///     fn new(customer: i64) -> Result<Self, InvalidCustomer> {
+///         validate(customer)?;
+///         Ok(Self { customer })
+///     }
+/// }
+///
+/// #[coil::async_trait::async_trait]
+/// impl coil::PerformAsync for SendInvoice {
+///     async fn perform(self, env: Arc<Environment>, pool: &PgPool) -> Result<(), PerformError> {
+///         env.mailer.send_invoice(self.customer).await
+///     }
+/// }
+/// ```
+#[proc_macro_derive(Job, attributes(coil))]
+pub fn derive_job(item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as DeriveInput);
+    let options = match options::DeriveOptions::from_attrs(&item.attrs) {
+        Ok(options) => options,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    emit_errors(derive_job::expand(item, options))
 }
 
 fn emit_errors(result: Result<proc_macro2::TokenStream, Diagnostic>) -> TokenStream {
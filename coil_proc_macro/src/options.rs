@@ -0,0 +1,320 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+
+/// Options passed to `#[coil::background_job(...)]`, or to `#[coil(...)]` when deriving
+/// `coil::Job`
+#[derive(Default)]
+pub struct JobOptions {
+    name: Option<syn::LitStr>,
+    aliases: Option<Vec<syn::LitStr>>,
+    priority: Option<syn::Expr>,
+    queue: Option<syn::Expr>,
+    max_retries: Option<syn::Expr>,
+    /// The timeout in milliseconds
+    timeout: Option<u64>,
+    unique: Option<bool>,
+    /// Each instantiation to register, and the name it is stored under
+    register: Option<(syn::Ident, Vec<Instantiation>)>,
+}
+
+impl JobOptions {
+    /// The `JOB_TYPE` of the job, `default` unless it was given a `name`
+    pub fn job_type(&self, default: &str) -> TokenStream {
+        match &self.name {
+            Some(name) => quote!(#name),
+            None => quote!(#default),
+        }
+    }
+
+    /// The `register` option, if it was given
+    pub fn register(&self) -> Option<&syn::Ident> {
+        self.register.as_ref().map(|(ident, _)| ident)
+    }
+
+    /// `register_job!` invocations and `JobInstantiation` impls for each instantiation of the
+    /// generic `job` listed in `register(...)`
+    pub fn registrations(&self, job: &syn::Ident) -> TokenStream {
+        let instantiations = self.register.iter().flat_map(|(_, i)| i);
+        let args: Vec<_> = instantiations.clone().map(|i| &i.args).collect();
+        let names = instantiations.map(|i| &i.name);
+        // Jobs enqueued under the bare job type are run by the first instantiation listed
+        let legacy = (0..args.len()).map(|i| i == 0);
+        quote! {
+            #(
+                impl coil::JobInstantiation for #job #args {
+                    const NAME: &'static str = #names;
+                    const LEGACY: bool = #legacy;
+                }
+                coil::register_job!(#job #args);
+            )*
+        }
+    }
+
+    /// The associated items these options generate in the `coil::Job` impl
+    pub fn job_items(&self) -> TokenStream {
+        let priority = self
+            .priority
+            .as_ref()
+            .map(|p| quote!(const PRIORITY: i32 = #p;));
+        let queue = self
+            .queue
+            .as_ref()
+            .map(|q| quote!(const QUEUE: &'static str = #q;));
+        let max_retries = self
+            .max_retries
+            .as_ref()
+            .map(|m| quote!(const MAX_RETRIES: Option<u32> = Some(#m);));
+        let timeout = self.timeout.map(|millis| {
+            quote!(
+                const TIMEOUT: Option<std::time::Duration> =
+                    Some(std::time::Duration::from_millis(#millis));
+            )
+        });
+        let aliases = self
+            .aliases
+            .as_ref()
+            .map(|a| quote!(const ALIASES: &'static [&'static str] = &[#(#a),*];));
+        let unique = self.unique.map(|u| quote!(const UNIQUE: bool = #u;));
+        quote! {
+            #aliases
+            #priority
+            #queue
+            #max_retries
+            #timeout
+            #unique
+        }
+    }
+}
+
+impl JobOptions {
+    fn set(&mut self, option: JobOption) -> syn::Result<()> {
+        match option {
+            JobOption::Name(ident, name) => set_once(&mut self.name, ident, name),
+            JobOption::Aliases(ident, aliases) => set_once(&mut self.aliases, ident, aliases),
+            JobOption::Priority(ident, expr) => set_once(&mut self.priority, ident, expr),
+            JobOption::Queue(ident, expr) => set_once(&mut self.queue, ident, expr),
+            JobOption::MaxRetries(ident, expr) => set_once(&mut self.max_retries, ident, expr),
+            JobOption::Timeout(ident, millis) => set_once(&mut self.timeout, ident, millis),
+            JobOption::Unique(ident, unique) => set_once(&mut self.unique, ident, unique),
+            JobOption::Register(ident, args) => {
+                set_once(&mut self.register, ident.clone(), (ident, args))
+            }
+        }
+    }
+}
+
+impl Parse for JobOptions {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut options = JobOptions::default();
+        for option in Punctuated::<JobOption, syn::Token![,]>::parse_terminated(input)? {
+            options.set(option)?;
+        }
+        Ok(options)
+    }
+}
+
+/// Options passed to `#[coil(...)]` on a type deriving `coil::Job`
+#[derive(Default)]
+pub struct DeriveOptions {
+    /// The environment the job is run with, `()` if not given
+    pub env: Option<syn::Type>,
+    pub is_async: bool,
+    pub job: JobOptions,
+}
+
+impl DeriveOptions {
+    /// Collect the options of every `#[coil(...)]` attribute in `attrs`
+    pub fn from_attrs(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut options = DeriveOptions::default();
+        let mut is_async = None;
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("coil")) {
+            let parsed =
+                attr.parse_args_with(Punctuated::<DeriveOption, syn::Token![,]>::parse_terminated)?;
+            for option in parsed {
+                match option {
+                    DeriveOption::Env(ident, ty) => set_once(&mut options.env, ident, ty)?,
+                    DeriveOption::Async(token) => {
+                        let ident = syn::Ident::new("async", token.span);
+                        set_once(&mut is_async, ident, true)?
+                    }
+                    DeriveOption::Job(option) => options.job.set(option)?,
+                }
+            }
+        }
+        options.is_async = is_async.unwrap_or(false);
+        Ok(options)
+    }
+}
+
+enum DeriveOption {
+    Env(syn::Ident, syn::Type),
+    Async(syn::Token![async]),
+    Job(JobOption),
+}
+
+impl Parse for DeriveOption {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(syn::Token![async]) {
+            return Ok(DeriveOption::Async(input.parse()?));
+        }
+        if input.peek(syn::Ident) && input.fork().parse::<syn::Ident>()? == "env" {
+            let ident = input.parse()?;
+            input.parse::<syn::Token![=]>()?;
+            return Ok(DeriveOption::Env(ident, input.parse()?));
+        }
+        Ok(DeriveOption::Job(input.parse()?))
+    }
+}
+
+enum JobOption {
+    Name(syn::Ident, syn::LitStr),
+    Aliases(syn::Ident, Vec<syn::LitStr>),
+    Priority(syn::Ident, syn::Expr),
+    Queue(syn::Ident, syn::Expr),
+    MaxRetries(syn::Ident, syn::Expr),
+    Timeout(syn::Ident, u64),
+    Unique(syn::Ident, bool),
+    Register(syn::Ident, Vec<Instantiation>),
+}
+
+impl Parse for JobOption {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let ident: syn::Ident = input.parse()?;
+        match ident.to_string().as_str() {
+            "name" => {
+                input.parse::<syn::Token![=]>()?;
+                let name: syn::LitStr = input.parse()?;
+                if name.value().is_empty() {
+                    return Err(syn::Error::new(name.span(), "the job name cannot be empty"));
+                }
+                Ok(JobOption::Name(ident, name))
+            }
+            "aliases" => {
+                input.parse::<syn::Token![=]>()?;
+                let content;
+                syn::bracketed!(content in input);
+                let aliases =
+                    Punctuated::<syn::LitStr, syn::Token![,]>::parse_terminated(&content)?;
+                if let Some(alias) = aliases.iter().find(|alias| alias.value().is_empty()) {
+                    return Err(syn::Error::new(alias.span(), "a job alias cannot be empty"));
+                }
+                Ok(JobOption::Aliases(ident, aliases.into_iter().collect()))
+            }
+            "priority" => {
+                input.parse::<syn::Token![=]>()?;
+                Ok(JobOption::Priority(ident, input.parse()?))
+            }
+            "queue" => {
+                input.parse::<syn::Token![=]>()?;
+                Ok(JobOption::Queue(ident, input.parse()?))
+            }
+            "max_retries" => {
+                input.parse::<syn::Token![=]>()?;
+                Ok(JobOption::MaxRetries(ident, input.parse()?))
+            }
+            "timeout" => {
+                input.parse::<syn::Token![=]>()?;
+                let lit: syn::LitStr = input.parse()?;
+                let millis = parse_duration(&lit.value())
+                    .ok_or_else(|| syn::Error::new(lit.span(), "expected a duration like \"30s\", with one of the units `ms`, `s`, `m` or `h`"))?;
+                Ok(JobOption::Timeout(ident, millis))
+            }
+            "unique" => {
+                if input.parse::<Option<syn::Token![=]>>()?.is_some() {
+                    let unique: syn::LitBool = input.parse()?;
+                    Ok(JobOption::Unique(ident, unique.value))
+                } else {
+                    Ok(JobOption::Unique(ident, true))
+                }
+            }
+            "register" => {
+                let content;
+                syn::parenthesized!(content in input);
+                let instantiations =
+                    Punctuated::<Instantiation, syn::Token![,]>::parse_terminated(&content)?;
+                for (i, instantiation) in instantiations.iter().enumerate() {
+                    let name = instantiation.name.value();
+                    if instantiations
+                        .iter()
+                        .take(i)
+                        .any(|other| other.name.value() == name)
+                    {
+                        return Err(syn::Error::new(
+                            instantiation.name.span(),
+                            format!("more than one instantiation is named `{}`", name),
+                        ));
+                    }
+                }
+                Ok(JobOption::Register(
+                    ident,
+                    instantiations.into_iter().collect(),
+                ))
+            }
+            _ => Err(syn::Error::new(
+                ident.span(),
+                format!("unknown coil option `{}`", ident),
+            )),
+        }
+    }
+}
+
+/// One instantiation in `register(...)`: either the type of a job's only type parameter, like
+/// `String`, or all of its generic arguments in angle brackets, like `<String, u32>`, followed by
+/// the name it is stored under, like `String = "string"`
+struct Instantiation {
+    args: syn::AngleBracketedGenericArguments,
+    name: syn::LitStr,
+}
+
+impl Parse for Instantiation {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let args = if input.peek(syn::Token![<]) {
+            input.parse()?
+        } else {
+            let ty: syn::Type = input.parse()?;
+            syn::parse_quote!(<#ty>)
+        };
+        if !input.peek(syn::Token![=]) {
+            return Err(input.error(
+                "each instantiation needs a name to be stored under, like `String = \"string\"`",
+            ));
+        }
+        input.parse::<syn::Token![=]>()?;
+        let name: syn::LitStr = input.parse()?;
+        if name.value().is_empty() {
+            return Err(syn::Error::new(
+                name.span(),
+                "the instantiation name cannot be empty",
+            ));
+        }
+        Ok(Instantiation { args, name })
+    }
+}
+
+/// Parse a duration like `"500ms"` or `"30s"` into milliseconds
+fn parse_duration(s: &str) -> Option<u64> {
+    let split = s.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = s.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    let millis_per_unit = match unit.trim() {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        _ => return None,
+    };
+    amount.checked_mul(millis_per_unit)
+}
+
+fn set_once<T>(slot: &mut Option<T>, ident: syn::Ident, value: T) -> syn::Result<()> {
+    if slot.is_some() {
+        return Err(syn::Error::new(
+            ident.span(),
+            format!("option `{}` specified more than once", ident),
+        ));
+    }
+    *slot = Some(value);
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use anyhow::Result;
+use coil::admin::{self, JobCount, JobFilter, JobState};
+use std::time::Duration;
+
+use crate::dummy_jobs::*;
+use crate::test_guard::TestGuard;
+
+#[coil::background_job(max_retries = 0)]
+fn die() -> Result<(), coil::PerformError> {
+    Err("died on purpose".into())
+}
+
+#[test]
+fn jobs_can_be_inspected_and_administered() -> Result<()> {
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let runner = TestGuard::builder(())
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .build();
+    log::info!("RUNNING `jobs_can_be_inspected_and_administered`");
+    let pool = runner.connection_pool();
+    smol::block_on(async {
+        failure_job().enqueue(&pool).await?;
+        die().enqueue(&pool).await?;
+        assert_eq!(2, runner.run_all_sync_tasks().await?);
+        assert_eq!(Err(coil::FailedJobsError::JobsFailed(1)), runner.check_for_failed_jobs(rx, 2).await);
+        noop_job().enqueue_in(Duration::from_secs(3600), &pool).await?;
+        async_noop_job().enqueue(&pool).await?;
+
+        assert_eq!(3, admin::list_jobs(&pool, &JobFilter::new()).await?.len());
+        let failed = admin::list_jobs(&pool, &JobFilter::new().failed_only()).await?;
+        assert_eq!(1, failed.len());
+        assert_eq!("failure_job", failed[0].job_type);
+        assert_eq!(JobState::Failed, failed[0].state);
+        assert_eq!(Some("fail on purpose"), failed[0].last_error.as_deref());
+        let scheduled = admin::list_jobs(&pool, &JobFilter::new().job_type("noop_job")).await?;
+        assert_eq!(JobState::Scheduled, scheduled[0].state);
+        assert!(admin::get_job(&pool, -1).await?.is_none());
+        let summaries = admin::list_job_summaries(&pool, &JobFilter::new().failed_only()).await?;
+        assert_eq!((failed[0].id, JobState::Failed), (summaries[0].id, summaries[0].state));
+        assert_eq!(failed[0].last_error, summaries[0].last_error);
+
+        let dead = admin::dead_jobs(&pool).await?.remove(0);
+        assert_eq!(Some("died on purpose"), admin::get_dead_job(&pool, dead.id).await?.map(|job| job.error).as_deref());
+        assert!(admin::get_dead_job(&pool, failed[0].id).await?.is_none());
+        let dead_summaries = admin::dead_job_summaries(&pool, &[dead.id, failed[0].id]).await?;
+        assert_eq!(1, dead_summaries.len());
+        assert_eq!((JobState::Dead, Some("died on purpose")), (dead_summaries[0].state, dead_summaries[0].last_error.as_deref()));
+
+        let count = |job_type: &str, state, count| JobCount { job_type: job_type.to_string(), state, count };
+        assert_eq!(
+            vec![
+                count("async_noop_job", JobState::Pending, 1),
+                count("die", JobState::Dead, 1),
+                count("failure_job", JobState::Failed, 1),
+                count("noop_job", JobState::Scheduled, 1),
+            ],
+            admin::count_jobs(&pool).await?
+        );
+
+        assert!(admin::reset_retries(&pool, failed[0].id).await?);
+        assert_eq!(0, admin::get_job(&pool, failed[0].id).await?.unwrap().retries);
+        assert!(admin::retry_job(&pool, scheduled[0].id).await?);
+        assert_eq!(JobState::Pending, admin::get_job(&pool, scheduled[0].id).await?.unwrap().state);
+        assert_eq!(1, admin::retry_jobs(&pool, &JobFilter::new().job_type("die")).await?);
+        assert!(admin::dead_jobs(&pool).await?.is_empty());
+        let revived = admin::list_jobs(&pool, &JobFilter::new().job_type("die")).await?;
+        assert_eq!((JobState::Pending, 0), (revived[0].state, revived[0].retries));
+
+        assert_eq!(1, admin::delete_jobs(&pool, &JobFilter::new().queue("default").limit(1)).await?);
+        assert!(admin::get_job(&pool, failed[0].id).await?.is_none());
+        assert!(admin::delete_job(&pool, revived[0].id).await?);
+        assert!(!admin::delete_job(&pool, revived[0].id).await?);
+        assert_eq!(2, admin::list_jobs(&pool, &JobFilter::new()).await?.len());
+        Ok(())
+    })
+}
+
+#[test]
+fn requeued_jobs_start_over() -> Result<()> {
+    crate::initialize();
+    let (runner, rx) = TestGuard::dummy_runner();
+    log::info!("RUNNING `requeued_jobs_start_over`");
+    let pool = runner.connection_pool();
+    smol::block_on(async {
+        failure_job().enqueue(&pool).await?;
+        assert_eq!(1, runner.run_all_sync_tasks().await?);
+        assert_eq!(Err(coil::FailedJobsError::JobsFailed(1)), runner.check_for_failed_jobs(rx, 1).await);
+        // as if the job was claimed by a worker which hung
+        sqlx::query("UPDATE _background_tasks SET locked_by = 'hung', locked_until = NOW() + INTERVAL '1 hour'")
+            .execute(&pool)
+            .await?;
+        let job = admin::list_jobs(&pool, &JobFilter::new()).await?.remove(0);
+        assert_eq!((JobState::Running, Some("hung")), (job.state, job.locked_by.as_deref()));
+        assert!(job.run_at > job.created_at);
+
+        assert!(admin::requeue_job(&pool, job.id).await?);
+        let job = admin::get_job(&pool, job.id).await?.unwrap();
+        assert_eq!((JobState::Pending, 0, None), (job.state, job.retries, job.last_error));
+        assert_eq!((None, None), (job.locked_by, job.locked_until));
+        assert!(!admin::requeue_job(&pool, -1).await?);
+        Ok(())
+    })
+}
+
+#[test]
+fn retrying_jobs_takes_one_limit_and_brings_dead_jobs_back_whole() -> Result<()> {
+    crate::initialize();
+    let (runner, _) = TestGuard::dummy_runner();
+    log::info!("RUNNING `retrying_jobs_takes_one_limit_and_brings_dead_jobs_back_whole`");
+    let pool = runner.connection_pool();
+    smol::block_on(async {
+        for _ in 0..3 {
+            noop_job().enqueue(&pool).await?;
+        }
+        // two identical unique jobs ran out of retries, the first one is still queued
+        sqlx::query(
+            "WITH dead AS (
+                DELETE FROM _background_tasks WHERE id > (SELECT MIN(id) FROM _background_tasks)
+                RETURNING *
+            )
+            INSERT INTO _background_tasks_dead (
+                id, job_type, is_async, priority, queue, data, retries, error, created_at,
+                is_unique, trace_context
+            )
+            SELECT id, job_type, is_async, priority, queue, data, retries, 'died', created_at,
+                TRUE, 'trace'
+            FROM dead"
+        )
+        .execute(&pool)
+        .await?;
+
+        assert_eq!(2, admin::retry_jobs(&pool, &JobFilter::new().limit(2)).await?);
+        let dead = admin::dead_jobs(&pool).await?;
+        assert_eq!(1, dead.len());
+        let revived = sqlx::query_as::<_, (bool, Option<String>)>(
+            "SELECT is_unique, trace_context FROM _background_tasks ORDER BY id DESC LIMIT 1"
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!((true, Some("trace".to_string())), revived);
+
+        // the other one is dropped in favour of the identical job it was revived as
+        assert!(admin::retry_job(&pool, dead[0].id).await?);
+        assert!(admin::dead_jobs(&pool).await?.is_empty());
+        assert_eq!(2, admin::list_jobs(&pool, &JobFilter::new()).await?.len());
+        Ok(())
+    })
+}
@@ -153,10 +153,14 @@ fn proc_macro_accepts_arbitrary_where_clauses() {
         Ok(())
     }
     
+    impl coil::JobInstantiation for can_specify_where_clause::Job<String> {
+        const NAME: &'static str = "string";
+    }
+
     let (tx, rx) = channel::bounded(1);
     let runner = TestGuard::builder(())
         .register_job::<can_specify_where_clause::Job<String>>()
-        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)).unwrap(); })
+        .on_finish(move |_| { smol::block_on(tx.send(coil::Event::Dummy)).unwrap(); })
         .build();
     
     smol::block_on(async {
@@ -168,3 +172,205 @@ fn proc_macro_accepts_arbitrary_where_clauses() {
     });
 }
 
+
+#[test]
+fn jobs_can_be_given_a_name() {
+    #[coil::background_job(name = "reports.render")]
+    fn render_report(_id: i64) -> Result<(), coil::PerformError> {
+        Ok(())
+    }
+
+    assert_eq!(render_report::Job::JOB_TYPE, "reports.render");
+
+    let (runner, rx) = TestGuard::dummy_runner();
+    smol::run(async {
+        let pool = runner.connection_pool();
+        render_report(1).enqueue(&pool).await.unwrap();
+        let (job_type,): (String,) = sqlx::query_as("SELECT job_type FROM _background_tasks")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(job_type, "reports.render");
+
+        runner.run_all_sync_tasks().await.unwrap();
+        runner.check_for_failed_jobs(rx, 1).await.unwrap();
+    });
+}
+
+#[test]
+fn unique_jobs_are_not_enqueued_while_an_identical_job_is_queued() {
+    #[coil::background_job(unique)]
+    fn refresh_feed(_user: String) -> Result<(), coil::PerformError> {
+        Ok(())
+    }
+
+    let (runner, rx) = TestGuard::dummy_runner();
+    smol::run(async {
+        use coil::JobExt;
+        let pool = runner.connection_pool();
+        refresh_feed("tohru".into()).enqueue(&pool).await.unwrap();
+        refresh_feed("tohru".into()).enqueue(&pool).await.unwrap();
+        refresh_feed("kanna".into()).enqueue(&pool).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let batch = vec![refresh_feed("kanna".into()), refresh_feed("elma".into())];
+        refresh_feed::Job::enqueue_batch(batch, &mut conn).await.unwrap();
+
+        let (queued,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM _background_tasks")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(queued, 3);
+
+        runner.run_all_sync_tasks().await.unwrap();
+        runner.check_for_failed_jobs(rx, 3).await.unwrap();
+
+        // Once the job has run, an identical one may be enqueued again
+        refresh_feed("tohru".into()).enqueue(&pool).await.unwrap();
+        let (queued,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM _background_tasks")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(queued, 1);
+    });
+}
+
+#[test]
+fn generic_jobs_can_register_their_instantiations() {
+    #[coil::background_job(register(Vec<String> = "strings"))]
+    fn registered_generic_job<S>(_eng: &(), arg: S) -> Result<(), coil::PerformError>
+    where
+        S: Serialize + DeserializeOwned + Send + std::fmt::Debug,
+    {
+        assert!(!format!("{:?}", arg).is_empty());
+        Ok(())
+    }
+
+    let (runner, rx) = TestGuard::dummy_runner();
+    smol::run(async {
+        let pool = runner.connection_pool();
+        registered_generic_job(vec!["hello".to_string()]).enqueue(&pool).await.unwrap();
+
+        runner.run_all_sync_tasks().await.unwrap();
+        runner.check_for_failed_jobs(rx, 1).await.unwrap();
+    });
+}
+
+#[test]
+fn jobs_queued_under_an_alias_are_run() {
+    #[coil::background_job(name = "reports.render_pdf", aliases = ["render_pdf", "pdf"])]
+    fn render_pdf(_id: i64) -> Result<(), coil::PerformError> {
+        Ok(())
+    }
+
+    let (runner, rx) = TestGuard::dummy_runner();
+    smol::run(async {
+        let pool = runner.connection_pool();
+        render_pdf(1).enqueue(&pool).await.unwrap();
+        render_pdf(2).enqueue(&pool).await.unwrap();
+        render_pdf(3).enqueue(&pool).await.unwrap();
+        // As if they were enqueued before the job was renamed
+        sqlx::query("UPDATE _background_tasks SET job_type = 'render_pdf' WHERE id = (SELECT MIN(id) FROM _background_tasks)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE _background_tasks SET job_type = 'pdf' WHERE id = (SELECT MAX(id) FROM _background_tasks)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        runner.run_all_sync_tasks().await.unwrap();
+        runner.check_for_failed_jobs(rx, 3).await.unwrap();
+    });
+}
+
+#[test]
+fn each_instantiation_of_a_generic_job_is_its_own_job_type() {
+    #[coil::background_job(register(String = "string", u32 = "u32"))]
+    fn describe<T>(_env: &(), value: T) -> Result<(), coil::PerformError>
+    where
+        T: Serialize + DeserializeOwned + Send + std::fmt::Debug,
+    {
+        assert!(!format!("{:?}", value).is_empty());
+        Ok(())
+    }
+
+    assert_eq!(describe::Job::<String>::job_type(), "describe<string>");
+    assert_eq!(describe::Job::<u32>::job_type(), "describe<u32>");
+
+    let (tx, rx) = channel::unbounded();
+    let runner = TestGuard::builder(())
+        .register_job::<describe::Job<String>>()
+        .register_job::<describe::Job<u32>>()
+        .on_finish(move |_| { smol::block_on(tx.send(coil::Event::Dummy)).unwrap(); })
+        .build();
+
+    smol::run(async {
+        let pool = runner.connection_pool();
+        describe("a string".to_string()).enqueue(&pool).await.unwrap();
+        describe(42u32).enqueue(&pool).await.unwrap();
+
+        runner.run_all_sync_tasks().await.unwrap();
+        runner.check_for_failed_jobs(rx, 2).await.unwrap();
+    });
+}
+
+#[test]
+fn legacy_generic_jobs_are_run_by_the_first_instantiation_registered() {
+    #[coil::background_job(register(String = "string", u32 = "u32"))]
+    fn describe_legacy<T>(value: T) -> Result<(), coil::PerformError>
+    where
+        T: Serialize + DeserializeOwned + Send + std::fmt::Debug,
+    {
+        assert!(!format!("{:?}", value).is_empty());
+        Ok(())
+    }
+
+    let (runner, rx) = TestGuard::dummy_runner();
+    smol::run(async {
+        let pool = runner.connection_pool();
+        describe_legacy("legacy string".to_string())
+            .enqueue(&pool)
+            .await
+            .unwrap();
+        // As if it was enqueued before instantiations got their own job types
+        sqlx::query("UPDATE _background_tasks SET job_type = 'describe_legacy'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        runner.run_all_sync_tasks().await.unwrap();
+        runner.check_for_failed_jobs(rx, 1).await.unwrap();
+    });
+}
+
+pub mod exported_jobs {
+    use coil::PerformError;
+
+    #[coil::background_job]
+    pub fn exported_job(_id: i64) -> Result<(), PerformError> {
+        Ok(())
+    }
+
+    #[coil::background_job]
+    pub(super) fn parent_job(_id: i64) -> Result<(), PerformError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn generated_jobs_are_as_visible_as_their_function() {
+    let jobs: (exported_jobs::exported_job::Job, exported_jobs::parent_job::Job) = (
+        exported_jobs::exported_job(1),
+        exported_jobs::parent_job(2),
+    );
+
+    let (runner, rx) = TestGuard::dummy_runner();
+    smol::run(async {
+        let pool = runner.connection_pool();
+        jobs.0.enqueue(&pool).await.unwrap();
+        jobs.1.enqueue(&pool).await.unwrap();
+
+        runner.run_all_sync_tasks().await.unwrap();
+        runner.check_for_failed_jobs(rx, 2).await.unwrap();
+    });
+}
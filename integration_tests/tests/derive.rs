@@ -0,0 +1,106 @@
+use coil::{PerformError, PerformAsync};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use crate::dummy_jobs::*;
+use crate::test_guard::TestGuard;
+
+#[derive(Serialize, Deserialize, coil::Job)]
+#[coil(env = String)]
+struct GreetUser {
+    name: String,
+}
+
+impl GreetUser {
+    fn new(name: &str) -> Result<Self, &'static str> {
+        if name.is_empty() {
+            return Err("users have names");
+        }
+        Ok(Self { name: name.to_string() })
+    }
+}
+
+impl coil::Perform for GreetUser {
+    fn perform(self, env: &String, _pool: &PgPool) -> Result<(), PerformError> {
+        if self.name == *env {
+            Ok(())
+        } else {
+            Err(format!("{} isn't {}", self.name, env).into())
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, coil::Job)]
+#[coil(async, name = "billing.send_invoice", queue = "mailers", priority = 5)]
+struct SendInvoice {
+    customer: i64,
+}
+
+#[coil::async_trait::async_trait]
+impl PerformAsync for SendInvoice {
+    async fn perform(self, _env: Arc<()>, pool: &PgPool) -> Result<(), PerformError> {
+        sqlx::query("SELECT $1").bind(self.customer).execute(pool).await?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, coil::Job)]
+#[coil(register(u32 = "u32", String = "string"))]
+struct Echo<T: Send + std::fmt::Debug> {
+    value: T,
+}
+
+impl<T> coil::Perform for Echo<T>
+where
+    T: Serialize + DeserializeOwned + Send + std::fmt::Debug,
+    Self: coil::JobInstantiation,
+{
+    fn perform(self, _env: &(), _pool: &PgPool) -> Result<(), PerformError> {
+        assert!(!format!("{:?}", self.value).is_empty());
+        Ok(())
+    }
+}
+
+#[test]
+fn derived_jobs_use_their_options() {
+    assert_eq!(GreetUser::JOB_TYPE, "greet_user");
+    assert_eq!(SendInvoice::JOB_TYPE, "billing.send_invoice");
+    assert_eq!(SendInvoice::QUEUE, "mailers");
+    assert_eq!(SendInvoice::PRIORITY, 5);
+    assert_eq!(Echo::<u32>::job_type(), "echo<u32>");
+    assert_eq!(Echo::<String>::job_type(), "echo<string>");
+}
+
+#[test]
+fn derived_sync_jobs_are_run() {
+    assert!(GreetUser::new("").is_err());
+
+    let (runner, rx) = TestGuard::runner("tohru".to_string(), 2);
+    smol::run(async {
+        let pool = runner.connection_pool();
+        GreetUser::new("tohru").unwrap().enqueue(&pool).await.unwrap();
+        GreetUser::new("kanna").unwrap().enqueue(&pool).await.unwrap();
+        runner.run_all_sync_tasks().await.unwrap();
+    });
+
+    assert_eq!(
+        Err(coil::FailedJobsError::JobsFailed(1)),
+        smol::block_on(runner.check_for_failed_jobs(rx, 2))
+    );
+}
+
+#[test]
+fn derived_async_and_generic_jobs_are_run() {
+    let (runner, rx) = TestGuard::dummy_runner();
+    smol::run(async {
+        let pool = runner.connection_pool();
+        SendInvoice { customer: 7 }.enqueue(&pool).await.unwrap();
+        Echo { value: 3u32 }.enqueue(&pool).await.unwrap();
+        Echo { value: "three".to_string() }.enqueue(&pool).await.unwrap();
+
+        runner.run_all_sync_tasks().await.unwrap();
+        runner.run_all_async_tasks().await.unwrap();
+        runner.check_for_failed_jobs(rx, 3).await.unwrap();
+    });
+}
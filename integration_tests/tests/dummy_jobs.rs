@@ -10,6 +10,11 @@ pub fn barrier_job(env: &Barrier) -> Result<(), PerformError> {
     Ok(())
 }
 
+#[coil::background_job]
+pub fn noop_job() -> Result<(), PerformError> {
+    Ok(())
+}
+
 #[coil::background_job]
 pub fn failure_job() -> Result<(), PerformError> {
     Err(PerformError::from("fail on purpose".to_string()))
@@ -19,3 +24,13 @@ pub fn failure_job() -> Result<(), PerformError> {
 pub fn panic_job() -> Result<(), PerformError> {
     panic!()
 }
+
+#[coil::background_job]
+pub async fn async_panic_job() -> Result<(), PerformError> {
+    panic!("async panic on purpose")
+}
+
+#[coil::background_job]
+pub async fn async_noop_job() -> Result<(), PerformError> {
+    Ok(())
+}
@@ -3,6 +3,10 @@ mod dummy_jobs;
 mod runner;
 mod test_guard;
 mod codegen;
+mod trace;
+mod metrics;
+mod admin;
+mod derive;
 
 use coil::Job;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -34,6 +38,7 @@ impl futures::task::Spawn for Executor {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize)]
 struct Size {
     height: u32,
@@ -55,7 +60,7 @@ fn resize_image(_name: String) -> Result<(), coil::PerformError> {
     Ok(())
 }
 
-#[coil::background_job]
+#[coil::background_job(register(String = "string"))]
 fn resize_image_gen<E: Serialize + DeserializeOwned + Send + std::fmt::Display>(_some: E) -> Result<(), coil::PerformError> {
     Ok(())
 }
@@ -101,6 +106,11 @@ fn generic_jobs_can_be_enqueued() {
         resize_image_gen("papooz".to_string()).enqueue(&pool).await.unwrap();
         resize_image_gen("kaguya".to_string()).enqueue(&pool).await.unwrap();
         resize_image_gen("L".to_string()).enqueue(&pool).await.unwrap();
+        // As if it was enqueued before each instantiation got its own job type
+        sqlx::query("UPDATE _background_tasks SET job_type = 'resize_image_gen' WHERE id = (SELECT MIN(id) FROM _background_tasks)")
+            .execute(&pool)
+            .await
+            .unwrap();
         runner.run_all_sync_tasks().await.unwrap();
         runner.check_for_failed_jobs(rx, 5).await.unwrap();
     });
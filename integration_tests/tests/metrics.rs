@@ -0,0 +1,49 @@
+use anyhow::Result;
+
+use crate::dummy_jobs::*;
+use crate::test_guard::TestGuard;
+
+#[test]
+fn metrics_count_jobs_and_measure_queues() -> Result<()> {
+    #[coil::background_job(queue = "metrics", max_retries = 0)]
+    fn metered_job(fail: bool) -> Result<(), coil::PerformError> {
+        if fail {
+            return Err("failed on purpose".into());
+        }
+        Ok(())
+    }
+
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let runner = TestGuard::builder(())
+        .queues(&["metrics"])
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .build();
+    log::info!("RUNNING `metrics_count_jobs_and_measure_queues`");
+    let conn = runner.connection_pool();
+    smol::block_on(async {
+        metered_job(false).enqueue(&conn).await?;
+        metered_job(true).enqueue(&conn).await?;
+
+        let metrics = coil::render_metrics(&conn).await?;
+        assert!(metrics.contains("coil_jobs_enqueued_total{job_type=\"metered_job\"} 2\n"));
+        assert!(metrics.contains("# TYPE coil_queue_depth gauge\n"));
+        assert!(metrics.contains("coil_queue_depth{queue=\"metrics\"} 2\n"));
+        assert!(metrics.contains("coil_queue_oldest_job_age_seconds{queue=\"metrics\"} "));
+
+        assert_eq!(2, runner.run_all_sync_tasks().await?);
+        // the failed job ran out of retries, and was moved to the dead letter table
+        runner.check_for_failed_jobs(rx, 2).await.unwrap();
+
+        let metrics = coil::render_metrics(&conn).await?;
+        assert!(metrics.contains("coil_jobs_started_total{job_type=\"metered_job\"} 2\n"));
+        assert!(metrics.contains("coil_jobs_succeeded_total{job_type=\"metered_job\"} 1\n"));
+        assert!(metrics.contains("coil_jobs_failed_total{job_type=\"metered_job\"} 1\n"));
+        assert!(metrics.contains("coil_jobs_dead_total{job_type=\"metered_job\"} 1\n"));
+        assert!(metrics.contains("# TYPE coil_job_duration_seconds histogram\n"));
+        assert!(metrics.contains("coil_job_duration_seconds_count{job_type=\"metered_job\"} 2\n"));
+        assert!(metrics.contains("coil_job_queue_latency_seconds_bucket{job_type=\"metered_job\",le=\"+Inf\"} 2\n"));
+        assert!(!metrics.contains("coil_queue_depth{queue=\"metrics\"}"));
+        Ok(())
+    })
+}
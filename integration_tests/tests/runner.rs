@@ -9,6 +9,8 @@ use crate::dummy_jobs::*;
 use crate::sync::Barrier;
 use crate::test_guard::TestGuard;
 
+type JobLog = std::sync::Arc<antidote::Mutex<Vec<String>>>;
+
 #[test]
 fn run_all_pending_jobs_returns_when_all_jobs_enqueued() -> Result<()> {
     crate::initialize();
@@ -26,7 +28,7 @@ fn run_all_pending_jobs_returns_when_all_jobs_enqueued() -> Result<()> {
             .fetch_one(&conn)
             .await.unwrap()
             .0;
-        let unlocked_job_count = sqlx::query_as::<_, (i64,)>("SELECT id FROM _background_tasks FOR UPDATE SKIP LOCKED")
+        let unlocked_job_count = sqlx::query_as::<_, (i64,)>("SELECT id FROM _background_tasks WHERE locked_until IS NULL OR locked_until < NOW()")
             .fetch_all(&conn)
             .await
             .unwrap()
@@ -62,7 +64,7 @@ fn check_for_failed_jobs_blocks_until_all_queued_jobs_are_finished() -> Result<(
 
         let res = smol::block_on(async {
             futures::select! {
-                msg = rx0.next().fuse() => false,
+                _ = rx0.next().fuse() => false,
                 _ = timeout.fuse() => true
             }
         });
@@ -117,13 +119,57 @@ fn panicking_jobs_are_caught_and_treated_as_failures() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn run_all_pending_tasks_runs_sync_and_async_jobs() -> Result<()> {
+    crate::initialize();
+    let (runner, rx) = TestGuard::dummy_runner();
+    log::info!("RUNNING `run_all_pending_tasks_runs_sync_and_async_jobs`");
+    let conn = runner.connection_pool();
+    smol::run(async {
+        noop_job().enqueue(&conn).await?;
+        async_noop_job().enqueue(&conn).await?;
+        noop_job().enqueue(&conn).await?;
+        async_noop_job().enqueue(&conn).await?;
+
+        assert_eq!(4, runner.run_all_pending_tasks().await?);
+        runner.check_for_failed_jobs(rx, 4).await.unwrap();
+        let remaining = sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM _background_tasks")
+            .fetch_one(&conn)
+            .await?
+            .0;
+        assert_eq!(0, remaining);
+        Ok(())
+    })
+}
+
+#[test]
+fn panicking_async_jobs_are_caught_and_treated_as_failures() -> Result<()> {
+    crate::initialize();
+    let (runner, rx) = TestGuard::dummy_runner();
+    log::info!("RUNNING `panicking_async_jobs_are_caught_and_treated_as_failures`");
+    let conn = runner.connection_pool();
+    smol::run(async {
+        async_panic_job().enqueue(&conn).await?;
+
+        runner.run_all_async_tasks().await?;
+        assert_eq!(Err(coil::FailedJobsError::JobsFailed(1)), runner.check_for_failed_jobs(rx, 1).await);
+
+        let (retries, last_error) = sqlx::query_as::<_, (i32, String)>("SELECT retries, last_error FROM _background_tasks")
+            .fetch_one(&conn)
+            .await?;
+        assert_eq!(1, retries);
+        assert!(last_error.contains("async panic on purpose"), "{}", last_error);
+        Ok(())
+    })
+}
+
 #[test]
 fn run_all_pending_jobs_errs_if_jobs_dont_start_in_timeout() -> Result<()> {
     crate::initialize();
     let barrier = Barrier::new(2);
     let (tx, rx) = channel::bounded(3);
     // A runner with 1 thread where all jobs will hang indefinitely.
-    // The second job will never start.
+    // The second job isn't claimed until the first one is done.
     let runner = TestGuard::builder(barrier.clone())
         .num_threads(1)
         .max_tasks(1)
@@ -142,8 +188,10 @@ fn run_all_pending_jobs_errs_if_jobs_dont_start_in_timeout() -> Result<()> {
 
     // Make sure the jobs actually run so we don't panic on drop
     barrier.wait();
+    smol::block_on(runner.check_for_failed_jobs(rx.clone(), 1)).unwrap();
+    assert_eq!(1, smol::block_on(runner.run_all_sync_tasks())?);
     barrier.wait();
-    smol::block_on(runner.check_for_failed_jobs(rx, 2)).unwrap();
+    smol::block_on(runner.check_for_failed_jobs(rx, 1)).unwrap();
     Ok(())
 }
 
@@ -186,3 +234,758 @@ fn jobs_failing_to_load_doesnt_panic_threads() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn jobs_are_run_in_priority_order() -> Result<()> {
+    #[coil::background_job]
+    fn bulk_job(log: &JobLog, name: String) -> Result<(), coil::PerformError> {
+        log.lock().push(name);
+        Ok(())
+    }
+
+    #[coil::background_job(priority = 10)]
+    fn urgent_job(log: &JobLog, name: String) -> Result<(), coil::PerformError> {
+        log.lock().push(name);
+        Ok(())
+    }
+
+    crate::initialize();
+    let log = JobLog::default();
+    let (tx, rx) = channel::bounded(3);
+    let runner = TestGuard::builder(log.clone())
+        .num_threads(1)
+        .max_tasks(1)
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .build();
+    log::info!("RUNNING `jobs_are_run_in_priority_order`");
+    let conn = runner.connection_pool();
+    smol::block_on(async {
+        bulk_job("bulk".into()).enqueue(&conn).await?;
+        urgent_job("urgent".into()).enqueue(&conn).await?;
+        bulk_job("overridden".into()).enqueue_with_priority(20, &conn).await
+    })?;
+
+    smol::block_on(runner.run_all_sync_tasks())?;
+    smol::block_on(runner.check_for_failed_jobs(rx, 3)).unwrap();
+    assert_eq!(vec!["overridden", "urgent", "bulk"], *log.lock());
+    Ok(())
+}
+
+#[test]
+fn scheduled_jobs_are_not_run_before_they_are_due() -> Result<()> {
+    crate::initialize();
+    let (runner, rx) = TestGuard::single_worker_runner();
+    log::info!("RUNNING `scheduled_jobs_are_not_run_before_they_are_due`");
+    let conn = runner.connection_pool();
+    smol::block_on(async {
+        let an_hour = Duration::from_secs(60 * 60);
+        failure_job().enqueue_in(an_hour, &conn).await?;
+        failure_job().enqueue_at(std::time::SystemTime::now() + an_hour, &conn).await?;
+        panic_job().enqueue_at(std::time::SystemTime::now() + an_hour, &conn).await?;
+        noop_job().enqueue_in(Duration::from_secs(0), &conn).await
+    })?;
+
+    let queued = smol::block_on(runner.run_all_sync_tasks())?;
+    assert_eq!(1, queued);
+    smol::block_on(runner.check_for_failed_jobs(rx, 1)).unwrap();
+
+    let remaining = smol::block_on(
+        sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM _background_tasks").fetch_one(&conn),
+    )?
+    .0;
+    assert_eq!(3, remaining);
+    Ok(())
+}
+
+#[test]
+fn failed_jobs_are_not_retried_until_their_backoff_has_passed() -> Result<()> {
+    crate::initialize();
+    let (runner, rx) = TestGuard::single_worker_runner();
+    log::info!("RUNNING `failed_jobs_are_not_retried_until_their_backoff_has_passed`");
+    let conn = runner.connection_pool();
+    smol::block_on(failure_job().enqueue(&conn))?;
+
+    assert_eq!(1, smol::block_on(runner.run_all_sync_tasks())?);
+    assert_eq!(Err(coil::FailedJobsError::JobsFailed(1)), smol::block_on(runner.check_for_failed_jobs(rx, 1)));
+    assert_eq!(0, smol::block_on(runner.run_all_sync_tasks())?);
+    Ok(())
+}
+
+#[test]
+fn failed_jobs_are_retried_once_their_backoff_has_passed() -> Result<()> {
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let runner = TestGuard::builder(())
+        .backoff(coil::Backoff::constant(Duration::from_millis(200)))
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .build();
+    log::info!("RUNNING `failed_jobs_are_retried_once_their_backoff_has_passed`");
+    let conn = runner.connection_pool();
+    smol::block_on(failure_job().enqueue(&conn))?;
+
+    assert_eq!(1, smol::block_on(runner.run_all_sync_tasks())?);
+    assert_eq!(Err(coil::FailedJobsError::JobsFailed(1)), smol::block_on(runner.check_for_failed_jobs(rx.clone(), 1)));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(1, smol::block_on(runner.run_all_sync_tasks())?);
+    assert_eq!(Err(coil::FailedJobsError::JobsFailed(1)), smol::block_on(runner.check_for_failed_jobs(rx, 1)));
+    Ok(())
+}
+
+#[test]
+fn jobs_out_of_retries_are_moved_to_the_dead_letter_table() -> Result<()> {
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    // with a free worker left over, `run_all_sync_tasks` returns right after claiming the job
+    let runner = TestGuard::builder(())
+        .num_threads(2)
+        .max_tasks(2)
+        .backoff(coil::Backoff::none())
+        .max_retries(1)
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .build();
+    log::info!("RUNNING `jobs_out_of_retries_are_moved_to_the_dead_letter_table`");
+    let conn = runner.connection_pool();
+    smol::block_on(failure_job().enqueue(&conn))?;
+
+    assert_eq!(1, smol::block_on(runner.run_all_sync_tasks())?);
+    assert_eq!(Err(coil::FailedJobsError::JobsFailed(1)), smol::block_on(runner.check_for_failed_jobs(rx.clone(), 1)));
+    assert_eq!(1, smol::block_on(runner.run_all_sync_tasks())?);
+    smol::block_on(runner.check_for_failed_jobs(rx, 1)).unwrap();
+
+    smol::block_on(async {
+        let dead = coil::dead_jobs(&conn).await?;
+        assert_eq!(1, dead.len());
+        assert_eq!("failure_job", dead[0].job_type);
+        assert_eq!("fail on purpose", dead[0].error);
+        assert_eq!(2, dead[0].retries);
+
+        assert!(coil::resurrect_dead_job(&conn, dead[0].id).await?);
+        assert!(!coil::resurrect_dead_job(&conn, dead[0].id).await?);
+        assert!(coil::dead_jobs(&conn).await?.is_empty());
+        let retries = sqlx::query_as::<_, (i32,)>("SELECT retries FROM _background_tasks WHERE id = $1")
+            .bind(dead[0].id)
+            .fetch_one(&conn)
+            .await?
+            .0;
+        assert_eq!(0, retries);
+        Ok(())
+    })
+}
+
+#[test]
+fn jobs_can_override_max_retries() -> Result<()> {
+    #[coil::background_job(max_retries = 0)]
+    fn fail_once() -> Result<(), coil::PerformError> {
+        Err("failed the only attempt".into())
+    }
+
+    crate::initialize();
+    let (runner, rx) = TestGuard::dummy_runner();
+    log::info!("RUNNING `jobs_can_override_max_retries`");
+    let conn = runner.connection_pool();
+    smol::block_on(fail_once().enqueue(&conn))?;
+
+    smol::block_on(runner.run_all_sync_tasks())?;
+    smol::block_on(runner.check_for_failed_jobs(rx, 1)).unwrap();
+
+    smol::block_on(async {
+        assert_eq!(1, coil::dead_jobs(&conn).await?.len());
+        assert_eq!(1, coil::purge_dead_jobs(&conn).await?);
+        assert!(coil::dead_jobs(&conn).await?.is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn failed_attempts_record_their_error() -> Result<()> {
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let runner = TestGuard::builder(())
+        .keep_history(true)
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .build();
+    log::info!("RUNNING `failed_attempts_record_their_error`");
+    let conn = runner.connection_pool();
+    smol::block_on(failure_job().enqueue(&conn))?;
+    smol::block_on(panic_job().enqueue(&conn))?;
+
+    assert_eq!(2, smol::block_on(runner.run_all_sync_tasks())?);
+    assert_eq!(Err(coil::FailedJobsError::JobsFailed(2)), smol::block_on(runner.check_for_failed_jobs(rx, 2)));
+
+    smol::block_on(async {
+        let mut last_errors = sqlx::query_as::<_, (String,)>("SELECT last_error FROM _background_tasks ORDER BY id")
+            .fetch_all(&conn)
+            .await?
+            .into_iter()
+            .map(|r| r.0);
+        assert_eq!(Some("fail on purpose"), last_errors.next().as_deref());
+        assert!(last_errors.next().unwrap().starts_with("job panicked"));
+
+        let history = sqlx::query_as::<_, (String, i32, Option<String>, Option<String>, String)>(
+            "SELECT job_type, attempt, error, panic, worker FROM _background_tasks_history ORDER BY task_id"
+        )
+            .fetch_all(&conn)
+            .await?;
+        assert_eq!(2, history.len());
+        assert_eq!(("failure_job".to_string(), 1), (history[0].0.clone(), history[0].1));
+        assert_eq!(Some("fail on purpose"), history[0].2.as_deref());
+        assert_eq!(None, history[0].3);
+        assert_eq!("panic_job", history[1].0);
+        assert_eq!(None, history[1].2);
+        assert!(history[1].3.as_ref().unwrap().starts_with("job panicked"));
+        assert!(history[1].4.ends_with(&format!(":{}", std::process::id())));
+        Ok(())
+    })
+}
+
+#[test]
+fn hooks_are_told_how_each_attempt_went() -> Result<()> {
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let log = JobLog::default();
+    let outcomes = std::sync::Arc::new(antidote::Mutex::new(Vec::new()));
+    let (start_log, failure_log, exhausted_log, finish_log) = (log.clone(), log.clone(), log.clone(), log.clone());
+    let finished = outcomes.clone();
+    let runner = TestGuard::builder(())
+        .num_threads(2)
+        .max_tasks(2)
+        .backoff(coil::Backoff::none())
+        .max_retries(1)
+        .on_start(move |start| start_log.lock().push(format!("start {} {} {}", start.job_type, start.queue, start.attempt)))
+        .on_failure(move |outcome| failure_log.lock().push(format!("failure {}", outcome.attempt)))
+        .on_retry_exhausted(move |outcome| exhausted_log.lock().push(format!("exhausted {}", outcome.attempt)))
+        .on_finish(move |outcome| {
+            finish_log.lock().push(format!("finish {}", outcome.attempt));
+            finished.lock().push(outcome.clone());
+            let _ = smol::block_on(tx.send(coil::Event::Dummy));
+        })
+        .build();
+    log::info!("RUNNING `hooks_are_told_how_each_attempt_went`");
+    let conn = runner.connection_pool();
+    smol::block_on(failure_job().enqueue(&conn))?;
+
+    assert_eq!(1, smol::block_on(runner.run_all_sync_tasks())?);
+    assert_eq!(Err(coil::FailedJobsError::JobsFailed(1)), smol::block_on(runner.check_for_failed_jobs(rx.clone(), 1)));
+    assert_eq!(1, smol::block_on(runner.run_all_sync_tasks())?);
+    smol::block_on(runner.check_for_failed_jobs(rx, 1)).unwrap();
+
+    assert_eq!(
+        vec![
+            "start failure_job default 1", "failure 1", "finish 1",
+            "start failure_job default 2", "failure 2", "exhausted 2", "finish 2",
+        ],
+        *log.lock()
+    );
+    let outcomes = outcomes.lock();
+    assert_eq!(outcomes[0].id, outcomes[1].id);
+    assert_eq!("failure_job", outcomes[1].job_type);
+    assert!(!outcomes[1].is_success());
+    assert_eq!(Some(coil::JobFailure::Error("fail on purpose".into())), outcomes[1].failure);
+    Ok(())
+}
+
+struct LogMiddleware {
+    name: &'static str,
+    log: JobLog,
+}
+
+impl coil::JobMiddleware<()> for LogMiddleware {
+    fn perform_sync(&self, job: &coil::JobStart, _: &(), next: coil::NextSync<'_, ()>) -> Result<(), coil::PerformError> {
+        self.log.lock().push(format!("{} before {}", self.name, job.job_type));
+        let res = next.run();
+        self.log.lock().push(format!("{} after {}", self.name, job.job_type));
+        res
+    }
+
+    fn perform_async<'a>(
+        &'a self,
+        job: &'a coil::JobStart,
+        _: &'a std::sync::Arc<()>,
+        next: coil::NextAsync<'a, ()>,
+    ) -> std::pin::Pin<Box<dyn futures::Future<Output = Result<(), coil::PerformError>> + Send + 'a>> {
+        async move {
+            self.log.lock().push(format!("{} before {}", self.name, job.job_type));
+            let res = next.run().await;
+            self.log.lock().push(format!("{} after {}", self.name, job.job_type));
+            res
+        }
+        .boxed()
+    }
+}
+
+#[test]
+fn middleware_wraps_jobs_in_the_order_it_was_added() -> Result<()> {
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let log = JobLog::default();
+    let runner = TestGuard::builder(())
+        .middleware(LogMiddleware { name: "outer", log: log.clone() })
+        .middleware(LogMiddleware { name: "inner", log: log.clone() })
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .build();
+    log::info!("RUNNING `middleware_wraps_jobs_in_the_order_it_was_added`");
+    let conn = runner.connection_pool();
+
+    smol::run(async {
+        noop_job().enqueue(&conn).await?;
+        assert_eq!(1, runner.run_all_sync_tasks().await?);
+        runner.check_for_failed_jobs(rx.clone(), 1).await.unwrap();
+        async_noop_job().enqueue(&conn).await?;
+        assert_eq!(1, runner.run_all_async_tasks().await?);
+        runner.check_for_failed_jobs(rx, 1).await.unwrap();
+        Ok::<_, anyhow::Error>(())
+    })?;
+
+    assert_eq!(
+        vec![
+            "outer before noop_job", "inner before noop_job", "inner after noop_job", "outer after noop_job",
+            "outer before async_noop_job", "inner before async_noop_job",
+            "inner after async_noop_job", "outer after async_noop_job",
+        ],
+        *log.lock()
+    );
+    Ok(())
+}
+
+#[test]
+fn middleware_can_skip_jobs() -> Result<()> {
+    struct SkipFailures;
+
+    impl coil::JobMiddleware<()> for SkipFailures {
+        fn perform_sync(&self, job: &coil::JobStart, _: &(), next: coil::NextSync<'_, ()>) -> Result<(), coil::PerformError> {
+            if job.job_type == "failure_job" {
+                return Ok(());
+            }
+            next.run()
+        }
+    }
+
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let runner = TestGuard::builder(())
+        .middleware(SkipFailures)
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .build();
+    log::info!("RUNNING `middleware_can_skip_jobs`");
+    let conn = runner.connection_pool();
+    smol::block_on(failure_job().enqueue(&conn))?;
+
+    assert_eq!(1, smol::block_on(runner.run_all_sync_tasks())?);
+    smol::block_on(runner.check_for_failed_jobs(rx, 1)).unwrap();
+    Ok(())
+}
+
+#[test]
+fn runners_only_run_jobs_from_the_queues_they_serve() -> Result<()> {
+    #[coil::background_job(queue = "mailers")]
+    fn mailer_job(log: &JobLog) -> Result<(), coil::PerformError> {
+        log.lock().push("mailer_job".to_string());
+        Ok(())
+    }
+
+    #[coil::background_job(queue = "images")]
+    fn image_job(log: &JobLog) -> Result<(), coil::PerformError> {
+        log.lock().push("image_job".to_string());
+        Ok(())
+    }
+
+    crate::initialize();
+    let log = JobLog::default();
+    let (tx, rx) = channel::unbounded();
+    let runner = TestGuard::builder(log.clone())
+        .queues(&["mailers", "default"])
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .build();
+    log::info!("RUNNING `runners_only_run_jobs_from_the_queues_they_serve`");
+    let conn = runner.connection_pool();
+    smol::block_on(async {
+        image_job().enqueue(&conn).await?;
+        mailer_job().enqueue(&conn).await
+    })?;
+
+    assert_eq!(1, smol::block_on(runner.run_all_sync_tasks())?);
+    smol::block_on(runner.check_for_failed_jobs(rx, 1)).unwrap();
+    assert_eq!(vec!["mailer_job".to_string()], *log.lock());
+
+    let queues = smol::block_on(
+        sqlx::query_as::<_, (String,)>("SELECT queue FROM _background_tasks").fetch_all(&conn)
+    )?;
+    assert_eq!(vec![("images".to_string(),)], queues);
+    Ok(())
+}
+
+#[test]
+fn run_forever_wakes_up_when_jobs_are_enqueued() -> Result<()> {
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let runner = TestGuard::builder(())
+        .poll_interval(Duration::from_secs(60 * 60))
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .build();
+    log::info!("RUNNING `run_forever_wakes_up_when_jobs_are_enqueued`");
+    let conn = runner.connection_pool();
+
+    smol::block_on(async {
+        let run = runner.run_forever().fuse();
+        let enqueue_and_wait = async {
+            // give the runner time to start listening and go to sleep on an empty queue
+            timer::Delay::new(Duration::from_millis(500)).await;
+            for _ in 0..2 {
+                noop_job().enqueue(&conn).await?;
+                let mut finished = rx.recv().fuse();
+                let mut timeout = timer::Delay::new(Duration::from_secs(5)).fuse();
+                futures::select! {
+                    _ = finished => {},
+                    _ = timeout => panic!("job was not run after being enqueued"),
+                }
+            }
+            Ok::<_, anyhow::Error>(())
+        }.fuse();
+        futures::pin_mut!(run, enqueue_and_wait);
+        futures::select! {
+            res = run => panic!("runner stopped: {:?}", res),
+            res = enqueue_and_wait => res,
+        }
+    })?;
+
+    let remaining = smol::block_on(
+        sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM _background_tasks").fetch_one(&conn)
+    )?;
+    assert_eq!(0, remaining.0);
+    Ok(())
+}
+
+#[test]
+fn shutdown_waits_for_running_jobs_and_reports_unfinished_ones() -> Result<()> {
+    #[coil::background_job]
+    fn sleep_job(millis: u64) -> Result<(), coil::PerformError> {
+        thread::sleep(Duration::from_millis(millis));
+        Ok(())
+    }
+
+    crate::initialize();
+    let runner = TestGuard::builder(())
+        .num_threads(2)
+        .max_tasks(2)
+        .timeout(Duration::from_millis(100))
+        .build();
+    log::info!("RUNNING `shutdown_waits_for_running_jobs_and_reports_unfinished_ones`");
+    let conn = runner.connection_pool();
+    let handle = runner.shutdown_handle();
+
+    let ids = smol::block_on(async {
+        sleep_job(100).enqueue(&conn).await?;
+        sleep_job(3000).enqueue(&conn).await?;
+        let ids = sqlx::query_as::<_, (i64,)>("SELECT id FROM _background_tasks ORDER BY id")
+            .fetch_all(&conn)
+            .await?;
+        Ok::<_, anyhow::Error>(ids)
+    })?;
+    let _ = smol::block_on(runner.run_all_sync_tasks());
+    // wait for both jobs to be picked up
+    thread::sleep(Duration::from_millis(50));
+
+    let unfinished = smol::block_on(handle.shutdown(Duration::from_millis(500)));
+    assert!(handle.is_shutdown());
+    assert_eq!(vec![ids[1].0], unfinished);
+
+    smol::block_on(async {
+        sleep_job(0).enqueue(&conn).await?;
+        assert_eq!(0, runner.run_all_sync_tasks().await?);
+        let remaining = sqlx::query_as::<_, (i64,)>("SELECT id FROM _background_tasks ORDER BY id")
+            .fetch_all(&conn)
+            .await?;
+        // the short job was deleted, the long one is still running
+        assert_eq!(ids[1], remaining[0]);
+        assert_eq!(2, remaining.len());
+        Ok(())
+    })
+}
+
+#[test]
+fn run_forever_returns_once_shut_down() -> Result<()> {
+    crate::initialize();
+    let runner = TestGuard::builder(())
+        .poll_interval(Duration::from_secs(60 * 60))
+        .build();
+    log::info!("RUNNING `run_forever_returns_once_shut_down`");
+    let handle = runner.shutdown_handle();
+
+    let (res, unfinished) = smol::block_on(futures::future::join(
+        runner.run_forever(),
+        async {
+            timer::Delay::new(Duration::from_millis(300)).await;
+            handle.shutdown(Duration::from_secs(1)).await
+        },
+    ));
+    res?;
+    assert!(unfinished.is_empty());
+    Ok(())
+}
+
+#[test]
+fn jobs_which_run_past_their_timeout_are_failed() -> Result<()> {
+    #[coil::background_job(timeout = "100ms")]
+    async fn slow_async_job() -> Result<(), coil::PerformError> {
+        timer::Delay::new(Duration::from_secs(5)).await;
+        Ok(())
+    }
+
+    #[coil::background_job]
+    fn cancellable_sync_job() -> Result<(), coil::PerformError> {
+        while !coil::is_cancelled() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let runner = TestGuard::builder(())
+        .job_timeout(Duration::from_millis(200))
+        .keep_history(true)
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .build();
+    log::info!("RUNNING `jobs_which_run_past_their_timeout_are_failed`");
+    let conn = runner.connection_pool();
+
+    smol::run(async {
+        slow_async_job().enqueue(&conn).await?;
+        cancellable_sync_job().enqueue(&conn).await?;
+
+        runner.run_all_async_tasks().await?;
+        runner.run_all_sync_tasks().await?;
+        assert_eq!(Err(coil::FailedJobsError::JobsFailed(2)), runner.check_for_failed_jobs(rx, 2).await);
+
+        let failed = sqlx::query_as::<_, (String, i32, String)>(
+            "SELECT job_type, retries, last_error FROM _background_tasks ORDER BY id"
+        )
+            .fetch_all(&conn)
+            .await?;
+        assert_eq!(("slow_async_job".to_string(), 1), (failed[0].0.clone(), failed[0].1));
+        assert_eq!("job timed out after 100ms", failed[0].2);
+        assert_eq!(("cancellable_sync_job".to_string(), 1), (failed[1].0.clone(), failed[1].1));
+        assert_eq!("job timed out after 200ms", failed[1].2);
+
+        let errors = sqlx::query_as::<_, (Option<String>, Option<String>)>(
+            "SELECT error, panic FROM _background_tasks_history ORDER BY task_id"
+        )
+            .fetch_all(&conn)
+            .await?;
+        assert_eq!(vec![(Some(failed[0].2.clone()), None), (Some(failed[1].2.clone()), None)], errors);
+        Ok(())
+    })
+}
+
+#[test]
+fn jobs_whose_lease_was_lost_are_reported_and_left_in_the_queue() -> Result<()> {
+    #[coil::background_job]
+    fn steal_own_lease(pool: &sqlx::PgPool) -> Result<(), coil::PerformError> {
+        // as if the lease expired, and the job was claimed again by the same worker
+        smol::block_on(
+            sqlx::query("UPDATE _background_tasks SET lease_token = 'another-claim'").execute(pool)
+        )?;
+        Ok(())
+    }
+
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let (error_tx, error_rx) = channel::unbounded();
+    let runner = TestGuard::builder(())
+        .num_threads(1)
+        .max_tasks(1)
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .on_error(move |e| { let _ = smol::block_on(error_tx.send(e)); })
+        .build();
+    log::info!("RUNNING `jobs_whose_lease_was_lost_are_reported_and_left_in_the_queue`");
+    let conn = runner.connection_pool();
+
+    smol::block_on(async {
+        steal_own_lease().enqueue(&conn).await?;
+        assert_eq!(1, runner.run_all_sync_tasks().await?);
+        let error = error_rx.recv().await?;
+        assert_matches!(error, coil::FinishError::Lost(_));
+        assert!(rx.try_recv().is_err());
+
+        let (id, lease_token) = sqlx::query_as::<_, (i64, String)>("SELECT id, lease_token FROM _background_tasks")
+            .fetch_one(&conn)
+            .await?;
+        assert_eq!(id, error.job_id());
+        assert_eq!("another-claim", lease_token);
+        Ok(())
+    })
+}
+
+#[test]
+fn failure_hooks_run_even_if_the_failure_could_not_be_recorded() -> Result<()> {
+    #[coil::background_job]
+    fn steal_own_lease_and_fail(pool: &sqlx::PgPool) -> Result<(), coil::PerformError> {
+        smol::block_on(
+            sqlx::query("UPDATE _background_tasks SET lease_token = 'another-claim'").execute(pool)
+        )?;
+        Err("fail on purpose".into())
+    }
+
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let (error_tx, error_rx) = channel::unbounded();
+    let (exhausted_tx, exhausted_rx) = channel::unbounded();
+    let log = JobLog::default();
+    let (failure_log, exhausted_log) = (log.clone(), log.clone());
+    let runner = TestGuard::builder(())
+        .num_threads(1)
+        .max_tasks(1)
+        .max_retries(0)
+        .on_failure(move |outcome| failure_log.lock().push(format!("failure {}", outcome.recorded)))
+        .on_retry_exhausted(move |outcome| {
+            exhausted_log.lock().push(format!("exhausted {}", outcome.recorded));
+            let _ = smol::block_on(exhausted_tx.send(()));
+        })
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .on_error(move |e| { let _ = smol::block_on(error_tx.send(e)); })
+        .build();
+    log::info!("RUNNING `failure_hooks_run_even_if_the_failure_could_not_be_recorded`");
+    let conn = runner.connection_pool();
+
+    smol::block_on(async {
+        steal_own_lease_and_fail().enqueue(&conn).await?;
+        assert_eq!(1, runner.run_all_sync_tasks().await?);
+        assert_matches!(error_rx.recv().await?, coil::FinishError::Lost(_));
+        exhausted_rx.recv().await?;
+        assert_eq!(vec!["failure false", "exhausted false"], *log.lock());
+        assert!(rx.try_recv().is_err());
+        Ok(())
+    })
+}
+
+#[test]
+fn recording_the_outcome_of_a_job_is_retried_if_its_connection_is_lost() -> Result<()> {
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let (error_tx, error_rx) = channel::unbounded();
+    let runner = TestGuard::builder(())
+        .num_threads(1)
+        .max_tasks(1)
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .on_error(move |e| { let _ = smol::block_on(error_tx.send(e)); })
+        .build();
+    log::info!("RUNNING `recording_the_outcome_of_a_job_is_retried_if_its_connection_is_lost`");
+    let conn = runner.connection_pool();
+
+    smol::block_on(async {
+        // the first write of the outcome fails, like it would if the connection dropped mid-way.
+        // sequences aren't rolled back, so the retry goes through
+        sqlx::query("CREATE SEQUENCE lose_connection_once").execute(&conn).await?;
+        sqlx::query(
+            "CREATE FUNCTION lose_connection_once() RETURNS TRIGGER AS $$
+            BEGIN
+                IF nextval('lose_connection_once') = 1 THEN
+                    RAISE EXCEPTION 'connection lost';
+                END IF;
+                RETURN OLD;
+            END $$ LANGUAGE plpgsql"
+        )
+        .execute(&conn)
+        .await?;
+        sqlx::query(
+            "CREATE TRIGGER lose_connection_once BEFORE DELETE ON _background_tasks
+            FOR EACH ROW EXECUTE FUNCTION lose_connection_once()"
+        )
+        .execute(&conn)
+        .await?;
+
+        noop_job().enqueue(&conn).await?;
+        assert_eq!(1, runner.run_all_sync_tasks().await?);
+        let finished = runner.check_for_failed_jobs(rx, 1).await;
+        for cleanup in &[
+            "DROP TRIGGER lose_connection_once ON _background_tasks",
+            "DROP FUNCTION lose_connection_once",
+            "DROP SEQUENCE lose_connection_once",
+        ] {
+            sqlx::query(cleanup).execute(&conn).await?;
+        }
+        finished.unwrap();
+        assert!(error_rx.try_recv().is_err());
+
+        let remaining = sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM _background_tasks")
+            .fetch_one(&conn)
+            .await?
+            .0;
+        assert_eq!(0, remaining);
+        Ok(())
+    })
+}
+
+#[test]
+fn jobs_whose_lease_expired_are_run_again() -> Result<()> {
+    crate::initialize();
+    let (runner, rx) = TestGuard::single_worker_runner();
+    log::info!("RUNNING `jobs_whose_lease_expired_are_run_again`");
+    let conn = runner.connection_pool();
+
+    smol::block_on(async {
+        noop_job().enqueue(&conn).await?;
+        noop_job().enqueue(&conn).await?;
+        // the first job was claimed by a worker which died, the second one is still running
+        sqlx::query(
+            "UPDATE _background_tasks SET locked_by = 'dead-worker', locked_until = NOW() - INTERVAL '1 second'
+            WHERE id = (SELECT MIN(id) FROM _background_tasks)"
+        )
+            .execute(&conn)
+            .await?;
+        sqlx::query(
+            "UPDATE _background_tasks SET locked_by = 'live-worker', locked_until = NOW() + INTERVAL '1 hour'
+            WHERE id = (SELECT MAX(id) FROM _background_tasks)"
+        )
+            .execute(&conn)
+            .await?;
+
+        assert_eq!(1, runner.run_all_sync_tasks().await?);
+        runner.check_for_failed_jobs(rx, 1).await.unwrap();
+        let remaining = sqlx::query_as::<_, (String,)>("SELECT locked_by FROM _background_tasks")
+            .fetch_all(&conn)
+            .await?;
+        assert_eq!(vec![("live-worker".to_string(),)], remaining);
+        Ok(())
+    })
+}
+
+#[test]
+fn running_jobs_keep_their_lease() -> Result<()> {
+    #[coil::background_job]
+    fn outlive_lease() -> Result<(), coil::PerformError> {
+        thread::sleep(Duration::from_millis(1000));
+        Ok(())
+    }
+
+    crate::initialize();
+    let (tx, rx) = channel::unbounded();
+    let (error_tx, error_rx) = channel::unbounded();
+    let runner = TestGuard::builder(())
+        .num_threads(2)
+        .max_tasks(1)
+        .lease(Duration::from_millis(300))
+        .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+        .on_error(move |e| { let _ = smol::block_on(error_tx.send(e)); })
+        .build();
+    log::info!("RUNNING `running_jobs_keep_their_lease`");
+    let conn = runner.connection_pool();
+
+    smol::block_on(async {
+        outlive_lease().enqueue(&conn).await?;
+        assert_eq!(1, runner.run_all_sync_tasks().await?);
+        thread::sleep(Duration::from_millis(600));
+
+        let leased = sqlx::query_as::<_, (bool,)>("SELECT locked_until > NOW() FROM _background_tasks")
+            .fetch_one(&conn)
+            .await?
+            .0;
+        assert!(leased);
+        // another worker can't claim the job
+        assert_eq!(0, runner.run_all_sync_tasks().await?);
+
+        runner.check_for_failed_jobs(rx, 1).await.unwrap();
+        assert!(error_rx.try_recv().is_err());
+        Ok(())
+    })
+}
@@ -12,6 +12,9 @@ use sqlx::Connection;
 // we just lock these tests instead.
 static TEST_MUTEX: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
+/// How long a finished test waits for the jobs its runner is still running
+const DRAIN_DEADLINE: Duration = Duration::from_secs(10);
+
 pub struct TestGuard<'a, Env: 'static> {
     runner: Runner<Env>,
     _lock: MutexGuard<'a, ()>,
@@ -47,6 +50,17 @@ impl<'a> TestGuard<'a, ()> {
          .build(),
          rx)
     }
+
+    /// A runner with a single worker, so jobs are run one after another.
+    pub fn single_worker_runner() -> (Self, channel::Receiver<coil::Event>) {
+        let (tx, rx) = channel::unbounded();
+        (Self::builder(())
+         .num_threads(1)
+         .max_tasks(1)
+         .on_finish(move |_| { let _ = smol::block_on(tx.send(coil::Event::Dummy)); })
+         .build(),
+         rx)
+    }
 }
 
 pub struct GuardBuilder<Env: 'static> {
@@ -69,13 +83,37 @@ impl<Env> GuardBuilder<Env> {
         self
     }
 
+    pub fn middleware(mut self, middleware: impl coil::JobMiddleware<Env>) -> Self {
+        self.builder = self.builder.middleware(middleware);
+        self
+    }
+
+    pub fn on_start(mut self, on_start: impl Fn(&coil::JobStart) + Send + Sync + 'static) -> Self {
+        self.builder = self.builder.on_start(on_start);
+        self
+    }
+
     /// Provide a hook that runs after a job has finished and all destructors have run
-    /// the `on_finish` closure accepts the job ID that finished as an argument
-    pub fn on_finish(mut self, on_finish: impl Fn(i64) + Send + Sync + 'static) -> Self {
+    pub fn on_finish(mut self, on_finish: impl Fn(&coil::JobOutcome) + Send + Sync + 'static) -> Self {
         self.builder = self.builder.on_finish(on_finish);
         self
     }
 
+    pub fn on_failure(mut self, on_failure: impl Fn(&coil::JobOutcome) + Send + Sync + 'static) -> Self {
+        self.builder = self.builder.on_failure(on_failure);
+        self
+    }
+
+    pub fn on_retry_exhausted(mut self, on_retry_exhausted: impl Fn(&coil::JobOutcome) + Send + Sync + 'static) -> Self {
+        self.builder = self.builder.on_retry_exhausted(on_retry_exhausted);
+        self
+    }
+
+    pub fn on_error(mut self, on_error: impl Fn(coil::FinishError) + Send + Sync + 'static) -> Self {
+        self.builder = self.builder.on_error(on_error);
+        self
+    }
+
     /// Set a timeout in seconds.
     /// This is the maximum amount of time we will wait until classifying a task as a failure and updating the retry counter.
     pub fn timeout(mut self, timeout: Duration) -> Self {
@@ -83,6 +121,41 @@ impl<Env> GuardBuilder<Env> {
         self
     }
 
+    pub fn backoff(mut self, backoff: coil::Backoff) -> Self {
+        self.builder = self.builder.backoff(backoff);
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.builder = self.builder.max_retries(max_retries);
+        self
+    }
+
+    pub fn job_timeout(mut self, timeout: Duration) -> Self {
+        self.builder = self.builder.job_timeout(timeout);
+        self
+    }
+
+    pub fn queues(mut self, queues: &[&str]) -> Self {
+        self.builder = self.builder.queues(queues);
+        self
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.builder = self.builder.poll_interval(poll_interval);
+        self
+    }
+
+    pub fn lease(mut self, lease: Duration) -> Self {
+        self.builder = self.builder.lease(lease);
+        self
+    }
+
+    pub fn keep_history(mut self, keep_history: bool) -> Self {
+        self.builder = self.builder.keep_history(keep_history);
+        self
+    }
+
     pub fn build<'a>(self) -> TestGuard<'a, Env> {
         TestGuard {
             _lock: TEST_MUTEX.lock(),
@@ -108,10 +181,15 @@ impl<'a, Env> DerefMut for TestGuard<'a, Env> {
 // makes sure all Pg connections are closed and database is empty before running any other tests
 impl<'a, Env: 'static> Drop for TestGuard<'a, Env> {
     fn drop(&mut self) {
+        // jobs still running would record their outcome after the tables are truncated
+        let running = smol::run(self.runner.shutdown_handle().shutdown(DRAIN_DEADLINE));
+        if !running.is_empty() && !std::thread::panicking() {
+            panic!("jobs {:?} were still running when the test ended", running);
+        }
         smol::block_on(self.runner.connection_pool().close());
         let mut conn = smol::block_on(sqlx::PgConnection::connect(&crate::DATABASE_URL)).unwrap();
         smol::block_on(async {
-            sqlx::query("TRUNCATE TABLE _background_tasks")
+            sqlx::query("TRUNCATE TABLE _background_tasks, _background_tasks_dead, _background_tasks_history")
                 .execute(&mut conn)
                 .await
                 .unwrap()
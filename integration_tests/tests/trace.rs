@@ -0,0 +1,97 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Once;
+use once_cell::sync::Lazy;
+use tracing::field::{Field, Visit};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::dummy_jobs::*;
+use crate::test_guard::TestGuard;
+
+type Fields = HashMap<String, String>;
+
+/// Fields of every `coil.job` span
+static JOB_SPANS: Lazy<antidote::Mutex<Vec<Fields>>> = Lazy::new(Default::default);
+/// What `NamePropagator::extract` was called with
+static EXTRACTED: Lazy<antidote::Mutex<Vec<String>>> = Lazy::new(Default::default);
+/// The span `traced_job` ran in
+static JOB_RAN_IN: Lazy<antidote::Mutex<Vec<String>>> = Lazy::new(Default::default);
+
+static INIT: Once = Once::new();
+
+struct RecordJobSpans;
+
+impl<S: tracing::Subscriber + for<'a> LookupSpan<'a>> tracing_subscriber::Layer<S> for RecordJobSpans {
+    fn on_new_span(&self, attrs: &tracing::span::Attributes<'_>, _: &tracing::span::Id, _: Context<'_, S>) {
+        if attrs.metadata().name() == "coil.job" {
+            let mut fields = FieldsVisitor(HashMap::new());
+            attrs.record(&mut fields);
+            JOB_SPANS.lock().push(fields.0);
+        }
+    }
+}
+
+struct FieldsVisitor(Fields);
+
+impl Visit for FieldsVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+/// Carries the name of the span a job was enqueued from
+struct NamePropagator;
+
+impl coil::Propagator for NamePropagator {
+    fn inject(&self, span: &tracing::Span) -> Option<String> {
+        span.metadata().map(|m| m.name().to_string())
+    }
+
+    fn extract(&self, context: &str, span: &tracing::Span) {
+        let name = span.metadata().map(|m| m.name()).unwrap_or_default();
+        EXTRACTED.lock().push(format!("{} -> {}", context, name));
+    }
+}
+
+fn initialize() {
+    crate::initialize();
+    INIT.call_once(|| {
+        let subscriber = tracing_subscriber::registry().with(RecordJobSpans);
+        tracing::subscriber::set_global_default(subscriber).unwrap();
+        assert!(coil::set_propagator(NamePropagator));
+    });
+}
+
+#[test]
+fn jobs_run_in_a_span_continuing_the_trace_they_were_enqueued_from() -> Result<()> {
+    #[coil::background_job]
+    fn traced_job() -> Result<(), coil::PerformError> {
+        let current = tracing::Span::current();
+        JOB_RAN_IN.lock().push(current.metadata().map(|m| m.name()).unwrap_or_default().to_string());
+        Ok(())
+    }
+
+    initialize();
+    let (runner, rx) = TestGuard::dummy_runner();
+    log::info!("RUNNING `jobs_run_in_a_span_continuing_the_trace_they_were_enqueued_from`");
+    let conn = runner.connection_pool();
+    tracing::info_span!("request").in_scope(|| smol::block_on(traced_job().enqueue(&conn)))?;
+
+    assert_eq!(1, smol::block_on(runner.run_all_sync_tasks())?);
+    smol::block_on(runner.check_for_failed_jobs(rx, 1)).unwrap();
+
+    let spans = JOB_SPANS.lock();
+    let span = spans.iter().find(|s| s.get("job.type").map(String::as_str) == Some("traced_job")).unwrap();
+    assert_eq!(Some("1"), span.get("job.attempt").map(String::as_str));
+    assert_eq!(Some("default"), span.get("job.queue").map(String::as_str));
+    assert!(span.contains_key("job.id"));
+    assert!(EXTRACTED.lock().contains(&"request -> coil.job".to_string()));
+    assert_eq!(vec!["coil.job"], *JOB_RAN_IN.lock());
+    Ok(())
+}